@@ -1,49 +1,68 @@
 use crate::CommandResult;
 use crate::config::{Config, UserInfo};
-use crate::constants::{LOGIN_PATH, LOGOUT_PATH};
+use crate::constants::{LOGIN_PATH, LOGOUT_PATH, REFRESH_PATH};
 use crate::error;
 use crate::utils;
 
-use crate::http_mocking::MockingMiddleware;
 use async_trait::async_trait;
 
 use rand::Rng;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashMap;
 use std::io::Write;
 use std::iter;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How long to wait for the browser to finish logging in before giving up
+const LOGIN_CALLBACK_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Login codes are short; anything longer was pasted together with other text
+const MAX_LOGIN_CODE_LENGTH: usize = 64;
+
+/// Path on the local callback server that the browser is redirected to after logging in
+const LOGIN_CALLBACK_PATH: &str = "/callback";
+
+/// How long a connection to the callback server may take to send its request line. Browsers
+/// open connections ahead of time that they may never send anything on, and each one would
+/// otherwise hold up the connections after it.
+const CALLBACK_REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
 
 fn require_auth(config: &Config) -> Result<UserInfo, AuthError> {
-    config.user.as_ref().cloned().ok_or(AuthError::NotLoggedIn(
-        std::backtrace::Backtrace::capture().to_string(),
-    ))
+    config
+        .user
+        .as_ref()
+        .cloned()
+        .ok_or(AuthError::NotLoggedIn(error::capture_backtrace()))
 }
 
 #[derive(Debug)]
-enum AuthError {
-    NotLoggedIn(String),
-}
-
-impl error::WithBacktrace for AuthError {
-    fn backtrace(&self) -> &String {
-        match self {
-            AuthError::NotLoggedIn(s) => s,
-        }
-    }
+pub enum AuthError {
+    NotLoggedIn(Vec<error::Frame>),
+    SessionExpired(Vec<error::Frame>),
+    LoginTimedOut(Vec<error::Frame>),
 }
 
-impl error::CustomErrorTrait for AuthError {}
-
-impl From<AuthError> for Box<dyn error::CustomErrorTrait> {
-    fn from(error: AuthError) -> Self {
-        Box::new(error)
-    }
-}
+error::impl_custom_error!(
+    AuthError {
+        NotLoggedIn(),
+        SessionExpired(),
+        LoginTimedOut(),
+    },
+    exit_code = error::EXIT_CODE_AUTH
+);
 
 impl std::fmt::Display for AuthError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             AuthError::NotLoggedIn(_) => write!(f, "Please login first using 'pet login'"),
+            AuthError::SessionExpired(_) => write!(f, "Oops! Please login again!"),
+            AuthError::LoginTimedOut(_) => write!(
+                f,
+                "Login timed out. Please try again, or use 'pet login --no-browser' to enter the code by hand"
+            ),
         }
     }
 }
@@ -55,6 +74,20 @@ struct LoginResponse {
     username: String,
     email: String,
     token: String,
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    token_expires_at_ms: Option<u128>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RefreshResponse {
+    token: String,
+    /// Servers that rotate refresh tokens send a new one along with the token
+    #[serde(default)]
+    refresh_token: Option<String>,
+    #[serde(default)]
+    token_expires_at_ms: Option<u128>,
 }
 
 #[async_trait]
@@ -66,26 +99,106 @@ pub async fn execute_authenticated_command(
     config: &mut Config,
     command: impl AuthenticatedCommand,
 ) -> CommandResult {
-    let user = require_auth(&config)?;
+    let user = require_auth(config)?;
     command.execute(user, config).await
 }
 
+/// Send a request with the logged in user's token. If the token has expired (or the server
+/// answers 401) and there is a refresh token, the token is refreshed and the request is sent
+/// once more. When that is not possible the session is cleared.
+pub async fn send_authenticated(
+    config: &mut Config,
+    request: reqwest_middleware::RequestBuilder,
+) -> Result<reqwest::Response, Box<dyn error::CustomErrorTrait>> {
+    let user = require_auth(config)?;
+    let (token, refreshed) = if user.token_expired() {
+        (refresh_session(config).await?, true)
+    } else {
+        (user.token, false)
+    };
+
+    let retry_request = request.try_clone();
+    let response = utils::send_with_retry(request.bearer_auth(token)).await?;
+    if response.status().as_u16() != 401 {
+        return Ok(response);
+    }
+    let Some(retry_request) = retry_request.filter(|_| !refreshed) else {
+        return Err(end_session(config));
+    };
+    let token = refresh_session(config).await?;
+    let response = utils::send_with_retry(retry_request.bearer_auth(token)).await?;
+    if response.status().as_u16() == 401 {
+        return Err(end_session(config));
+    }
+    Ok(response)
+}
+
+/// Exchange the refresh token for a new token, saving it in the config
+async fn refresh_session(config: &mut Config) -> Result<String, Box<dyn error::CustomErrorTrait>> {
+    let Some(refresh_token) = config
+        .user
+        .as_ref()
+        .and_then(|user| user.refresh_token.clone())
+    else {
+        return Err(end_session(config));
+    };
+    utils::verbose!("Refreshing the login token");
+
+    let client = utils::build_client();
+    let response = utils::send_with_retry(
+        client
+            .post(utils::get_api_base_url() + REFRESH_PATH)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&json!({
+                "refresh_token": refresh_token
+            }))?),
+    )
+    .await?;
+    if response.status().is_client_error() {
+        return Err(end_session(config));
+    }
+    if !response.status().is_success() {
+        let error_text = response.text().await?;
+        return Err(format!("Failed to refresh your login: {}", error_text).into());
+    }
+
+    let refreshed: RefreshResponse = response.json().await?;
+    if let Some(user) = config.user.as_mut() {
+        user.token = refreshed.token.clone();
+        user.token_expires_at_ms = refreshed.token_expires_at_ms;
+        if refreshed.refresh_token.is_some() {
+            user.refresh_token = refreshed.refresh_token;
+        }
+    }
+    config.save()?;
+    Ok(refreshed.token)
+}
+
+/// Forget the user whose session can no longer be used
+fn end_session(config: &mut Config) -> Box<dyn error::CustomErrorTrait> {
+    config.user = None;
+    if let Err(e) = config.save() {
+        return e.into();
+    }
+    AuthError::SessionExpired(error::capture_backtrace()).into()
+}
+
 pub async fn do_logout(user: UserInfo, config: &mut Config) -> CommandResult {
-    println!("Logging out user with email: {}", user.email);
+    utils::info!("Logging out user with email: {}", user.email);
 
-    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
-        .with(MockingMiddleware)
-        .build();
-    let response = client
-        .post(utils::get_api_base_url() + LOGOUT_PATH)
-        .bearer_auth(user.token)
-        .send()
-        .await?;
+    let client = utils::build_client();
+    let response = utils::send_with_retry(
+        client
+            .post(utils::get_api_base_url() + LOGOUT_PATH)
+            .bearer_auth(user.token),
+    )
+    .await?;
 
     if response.status().is_success() || response.status().as_u16() == 401 {
+        config.delete_keychain_tokens(&user.email);
         config.user = None;
         config.save()?;
-        println!("Logged out successfully!");
+        utils::info!("Logged out successfully!");
         Ok(())
     } else {
         let error_text = response.text().await?;
@@ -93,37 +206,38 @@ pub async fn do_logout(user: UserInfo, config: &mut Config) -> CommandResult {
     }
 }
 
-pub async fn do_login(config: &mut Config) -> CommandResult {
+pub async fn do_login(config: &mut Config, no_browser: bool) -> CommandResult {
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
     let mut rng = rand::rng();
     let one_char = || CHARSET[rng.random_range(0..CHARSET.len())] as char;
     let random_string: String = iter::repeat_with(one_char).take(30).collect();
 
-    println!(
-        "Open the following URL in your browser: {}/auth/github?code={}",
-        utils::get_api_base_url(),
-        random_string
-    );
+    // The mock API has no login page to open
+    let browser_code = if no_browser || utils::mocking_enabled() {
+        None
+    } else {
+        login_with_browser(&random_string).await?
+    };
+    let code = match browser_code {
+        Some(code) => validate_login_code(&code)
+            .map_err(|e| format!("The browser sent an invalid login code: {}", e))?
+            .to_string(),
+        None => read_login_code(&random_string)?,
+    };
+    let code = code.as_str();
+    utils::info!("\nLogging in...");
 
-    print!("Once you login, you will see a code on your browser. Enter it here: ");
-    std::io::stdout().flush().unwrap();
-    let mut code = String::new();
-    std::io::stdin().read_line(&mut code).unwrap();
-    let code = code.trim();
-    println!("\nLogging in...");
-
-    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
-        .with(MockingMiddleware)
-        .build();
-    let response = client
-        .post(utils::get_api_base_url() + LOGIN_PATH)
-        .header("Content-Type", "application/json")
-        .body(serde_json::to_string(&json!({
-            "code": random_string,
-            "otp": code
-        }))?)
-        .send()
-        .await?;
+    let client = utils::build_client();
+    let response = utils::send_with_retry(
+        client
+            .post(utils::get_api_base_url() + LOGIN_PATH)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&json!({
+                "code": random_string,
+                "otp": code
+            }))?),
+    )
+    .await?;
 
     if response.status().is_success() {
         let login_response: LoginResponse = response.json().await?;
@@ -133,13 +247,200 @@ pub async fn do_login(config: &mut Config) -> CommandResult {
             username: login_response.username.clone(),
             email: login_response.email.clone(),
             token: login_response.token,
+            refresh_token: login_response.refresh_token,
+            token_expires_at_ms: login_response.token_expires_at_ms,
         });
         config.save()?;
 
-        println!("Successfully logged in as: {}", login_response.email);
+        utils::info!("Successfully logged in as: {}", login_response.email);
         Ok(())
     } else {
         let error_text = response.text().await?;
         Err(format!("Login failed: {}", error_text).into())
     }
 }
+
+/// Ask the user to open the login page and type in the code it shows, asking again until the
+/// code looks valid
+fn read_login_code(random_string: &str) -> Result<String, Box<dyn error::CustomErrorTrait>> {
+    println!(
+        "Open the following URL in your browser: {}/auth/github?code={}",
+        utils::get_api_base_url(),
+        random_string
+    );
+
+    print!("Once you login, you will see a code on your browser. Enter it here: ");
+    loop {
+        std::io::stdout().flush()?;
+        let mut code = String::new();
+        if std::io::stdin().read_line(&mut code)? == 0 {
+            return Err("Login cancelled: no code was entered".into());
+        }
+        match validate_login_code(&code) {
+            Ok(code) => return Ok(code.to_string()),
+            Err(e) => print!("{}. Please enter the code again: ", e),
+        }
+    }
+}
+
+/// Check the shape of a login code before sending it, so that typos and stray text are caught
+/// without a round trip. Returns the code without surrounding whitespace.
+fn validate_login_code(code: &str) -> Result<&str, String> {
+    let code = code.trim();
+    if code.is_empty() {
+        return Err("The code is empty".to_string());
+    }
+    if code.len() > MAX_LOGIN_CODE_LENGTH {
+        return Err(format!(
+            "The code is too long ({} characters), make sure you only copied the code",
+            code.len()
+        ));
+    }
+    if let Some(c) = code
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && *c != '-')
+    {
+        return Err(format!(
+            "The code contains '{}', but it can only contain letters, digits and dashes",
+            c
+        ));
+    }
+    Ok(code)
+}
+
+/// Open the login page in the browser, which redirects back to a short-lived server on
+/// localhost with the code once the user has logged in. Returns None if the server could not be
+/// started or the browser could not be opened, so that the code can be entered by hand instead.
+async fn login_with_browser(
+    random_string: &str,
+) -> Result<Option<String>, Box<dyn error::CustomErrorTrait>> {
+    let listener = match TcpListener::bind(("127.0.0.1", 0)).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            utils::verbose!("Could not start the login callback server: {}", e);
+            return Ok(None);
+        }
+    };
+    let port = listener.local_addr()?.port();
+    let url = format!(
+        "{}/auth/github?code={}&callback_port={}",
+        utils::get_api_base_url(),
+        random_string,
+        port
+    );
+    if let Err(e) = open::that(&url) {
+        utils::verbose!("Could not open the browser: {}", e);
+        return Ok(None);
+    }
+    utils::info!(
+        "Your browser was opened to login. If it did not open, visit: {}",
+        url
+    );
+
+    match tokio::time::timeout(
+        LOGIN_CALLBACK_TIMEOUT,
+        accept_login_callback(&listener, random_string),
+    )
+    .await
+    {
+        Ok(code) => Ok(Some(code)),
+        Err(_) => Err(AuthError::LoginTimedOut(error::capture_backtrace()).into()),
+    }
+}
+
+/// Serve requests until the browser is redirected to the callback path with the code. Requests
+/// that do not carry the random string of this login (e.g. from another tab) are ignored.
+async fn accept_login_callback(listener: &TcpListener, random_string: &str) -> String {
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let Ok(Some(target)) =
+            tokio::time::timeout(CALLBACK_REQUEST_TIMEOUT, read_request_target(&mut stream)).await
+        else {
+            continue;
+        };
+        let Ok(url) = reqwest::Url::parse(&format!("http://127.0.0.1{}", target)) else {
+            respond(&mut stream, "400 Bad Request", "Bad request").await;
+            continue;
+        };
+        let params: HashMap<String, String> = url.query_pairs().into_owned().collect();
+        if url.path() != LOGIN_CALLBACK_PATH
+            || params.get("code").map(String::as_str) != Some(random_string)
+        {
+            respond(&mut stream, "404 Not Found", "Not found").await;
+            continue;
+        }
+        let Some(code) = params.get("otp") else {
+            respond(&mut stream, "400 Bad Request", "The login code is missing").await;
+            continue;
+        };
+        respond(
+            &mut stream,
+            "200 OK",
+            "BitPet received your login. You can close this tab and go back to your terminal.",
+        )
+        .await;
+        return code.clone();
+    }
+}
+
+/// Read an HTTP request up to the end of its first line and return its target (path and query)
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(2).any(|window| window == b"\r\n") && request.len() < 8192 {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next()?.split_whitespace();
+    if request_line.next()? != "GET" {
+        return None;
+    }
+    request_line.next().map(str::to_string)
+}
+
+async fn respond(stream: &mut TcpStream, status: &str, message: &str) {
+    let body = format!(
+        "<!DOCTYPE html><html><body><p>{}</p></body></html>",
+        message
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn an_idle_connection_does_not_hold_up_the_login() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // Connected first and never sends anything, like a browser's preconnect
+        let _idle = TcpStream::connect(address).await.unwrap();
+        let mut browser = TcpStream::connect(address).await.unwrap();
+        browser
+            .write_all(b"GET /callback?code=random&otp=123456 HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n")
+            .await
+            .unwrap();
+
+        let code = tokio::time::timeout(
+            LOGIN_CALLBACK_TIMEOUT,
+            accept_login_callback(&listener, "random"),
+        )
+        .await
+        .expect("the login was held up by the idle connection");
+        assert_eq!(code, "123456");
+    }
+}
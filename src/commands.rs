@@ -1,36 +1,114 @@
 use clap::Subcommand;
 
+use crate::export::ExportFormat;
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     // Get the mood, health, and other details of your pet
     Status {},
 
     /// Feed your pet (based on your git commits since last feed)
-    Feed {},
+    Feed {
+        /// Only count commits after this date, in any format git understands (e.g. "2weeks",
+        /// "2024-01-31"). Defaults to the time of your last feed, or one week ago.
+        #[arg(long, value_parser = parse_since)]
+        since: Option<String>,
+
+        /// Only count the commits of this repo, which must be one of your added repos
+        #[arg(long, value_name = "PATH")]
+        repo: Option<String>,
+
+        /// Only show which commits would be counted, without feeding your pet
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Accept a coding challenge if your pet asks for one, without asking
+        #[arg(long, conflicts_with = "decline_challenges")]
+        accept_challenges: bool,
+
+        /// Decline a coding challenge if your pet asks for one, without asking
+        #[arg(long)]
+        decline_challenges: bool,
+    },
 
     /// Play with your pet (Makes it happy)
     Play {},
 
-    /// Add a git repo (will be used to fetch commits for feeding your pet)
+    /// Put your pet to sleep (Restores its energy)
+    Sleep {},
+
+    /// Show how your pet's stats changed over the last days
+    History {
+        /// Number of days to show
+        #[arg(long, default_value_t = 14, value_parser = clap::value_parser!(u32).range(1..=365))]
+        days: u32,
+    },
+
+    /// Export your pet's current animation to a file
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Svg)]
+        format: ExportFormat,
+
+        /// Path of the file to write
+        #[arg(long, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+        out: std::path::PathBuf,
+    },
+
+    /// Create a new pet
+    NewPet {
+        /// Name of the pet (you will be asked for one if not given)
+        #[arg(long)]
+        name: Option<String>,
+    },
+
+    /// Permanently delete your pet
+    RemovePet {},
+
+    /// Add git repos (will be used to fetch commits for feeding your pet). Paths may contain *
+    /// and ? wildcards.
     AddRepo {
-        path: String,
+        #[arg(required = true, value_hint = clap::ValueHint::DirPath)]
+        paths: Vec<String>,
+
+        /// Add every git repo found in the given directories and their subdirectories
+        #[arg(long, short)]
+        recursive: bool,
     },
 
     /// Remove a git repo (will not be used to fetch commits for feeding your pet)
     RemoveRepo {
+        #[arg(value_hint = clap::ValueHint::DirPath)]
         path: String,
     },
 
     /// List all the git repos from which commits will be fetched for feeding your pet
-    ListRepos {},
+    ListRepos {
+        /// Also count the commits in each repo that the next feed would count (runs git for
+        /// every repo, so it can be slow)
+        #[arg(long)]
+        with_commits: bool,
+    },
+
+    /// Manage the list of git repos
+    Repos {
+        #[command(subcommand)]
+        subcommand: ReposSubcommand,
+    },
 
     /// Login to your BitPet account
-    Login {},
+    Login {
+        /// Enter the login code by hand instead of opening a browser (e.g. over SSH)
+        #[arg(long)]
+        no_browser: bool,
+    },
 
     /// Logout from your BitPet account
     Logout {},
 
     /// Get the user information about who is logged in
+    ///
+    /// With --verbose, also shows where the config is, the state of the login token (but not
+    /// the token), the API, the number of repos and the last update check, which helps support
     Whoami {},
 
     /// Challenge related commands
@@ -39,17 +117,115 @@ pub enum Commands {
         subcommand: ChallengeSubcommand,
     },
 
+    /// Switch between your BitPet accounts (e.g. a personal and a work account)
+    Account {
+        #[command(subcommand)]
+        subcommand: AccountSubcommand,
+    },
+
+    /// View and change settings
+    Config {
+        #[command(subcommand)]
+        subcommand: ConfigSubcommand,
+    },
+
     Version {},
+
+    /// Check your setup (git, login, repos, ...) for problems
+    Doctor {},
+
+    /// Update BitPet to the latest version
+    Update {},
+
+    /// Print a shell completion script
+    #[command(hide = true)]
+    Completions {
+        shell: clap_complete::Shell,
+    },
+}
+
+/// Git accepts almost any text as a date (unknown words are ignored), so only reject what it
+/// would silently treat as "now"
+fn parse_since(since: &str) -> Result<String, String> {
+    let since = since.trim();
+    if since.is_empty() {
+        return Err("the date must not be empty".to_string());
+    }
+    Ok(since.to_string())
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigSubcommand {
+    /// Print the value of a setting
+    Get { key: String },
+
+    /// Change a setting
+    Set { key: String, value: String },
+
+    /// Print where the config file is. It is the --config path if given, else BITPET_CONFIG,
+    /// else $XDG_CONFIG_HOME/bitpet/config.json, else bitpet/config.json in the platform's
+    /// config dir.
+    Path {},
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ReposSubcommand {
+    /// Remove repos that no longer exist or are not git repos anymore
+    Prune {},
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AccountSubcommand {
+    /// List all profiles and the accounts logged in to them
+    List {},
+
+    /// Switch to another profile
+    Use { name: String },
+
+    /// Add a new profile and switch to it
+    Add { name: String },
+
+    /// Remove a profile (logs it out on this machine, the account and its pet are kept)
+    Remove { name: String },
 }
 
 #[derive(Subcommand, Debug)]
 pub enum ChallengeSubcommand {
-    /// Read the current challenge
+    /// Read all pending challenges
     Read {},
 
-    /// Answer the current challenge
-    Ans {},
+    /// Answer a pending challenge (asks which one if there are several and no ID is given)
+    Ans { id: Option<String> },
+
+    /// Remove a pending challenge (the ID can be left out if there is only one)
+    Remove { id: Option<String> },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn since_accepts_anything_git_does() {
+        let cases = [
+            ("2025-09-19", "2025-09-19"),
+            ("2025-09-19T20:12:42+05:30", "2025-09-19T20:12:42+05:30"),
+            ("2 weeks ago", "2 weeks ago"),
+            ("  yesterday ", "yesterday"),
+            ("@1758300000", "@1758300000"),
+        ];
+        for (since, expected) in cases {
+            assert_eq!(parse_since(since).as_deref(), Ok(expected), "{:?}", since);
+        }
+    }
 
-    /// Remove the current challenge
-    Remove {},
+    #[test]
+    fn since_rejects_an_empty_date() {
+        for since in ["", "   ", "\t\n"] {
+            assert_eq!(
+                parse_since(since),
+                Err("the date must not be empty".to_string())
+            );
+        }
+    }
 }
@@ -1,19 +1,191 @@
+use colored::Colorize;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
 
 use crate::error;
-use crate::pet::Challenge;
+use crate::pet::{Challenge, Pet};
+use crate::ui::{self, Animation};
 use crate::utils;
 
-#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(default)]
 pub struct Config {
-    /// User authentication information
+    /// User authentication information of the active profile. Only older configs have it at the
+    /// top level; it is saved in `profiles`.
+    #[serde(skip_serializing)]
     pub user: Option<UserInfo>,
+    /// Repos of the active profile, saved in `profiles` like `user`
+    #[serde(skip_serializing)]
     pub repos: Vec<String>,
-    pub challenge: Option<Challenge>,
+    /// Accounts that can be switched between with `pet account use`, by name. The active
+    /// profile is only kept in `user` and `repos` until the config is saved.
+    pub profiles: BTreeMap<String, Profile>,
+    pub active_profile: String,
+    /// Other emails you commit with (e.g. on another machine), counted as your commits too
+    pub extra_author_emails: Vec<String>,
+    /// Only count commits with a good GPG/SSH signature
+    pub require_signed: bool,
+    /// Multiplier for the speed of animations (1.0 plays them at their normal speed)
+    pub animation_speed: f32,
+    /// JSON file with a `PetTheme` for drawing the pet (the BITPET_THEME env var overrides it)
+    pub theme_path: Option<PathBuf>,
+    /// File that a line of JSON is appended to for each feed, play, sleep and challenge answer
+    /// (the BITPET_EVENT_LOG env var overrides it)
+    pub event_log_path: Option<PathBuf>,
+    /// How many times a request is retried after a connection error, or after a timeout or server
+    /// error when the request is safe to send again
+    pub network_retries: u32,
+    /// How long a request to the BitPet API may take before it is given up on
+    pub request_timeout_ms: u64,
+    /// URL of the BitPet API, for self hosted or staging backends
+    pub api_base_url: Option<String>,
+    /// How far back `pet feed` looks for commits the first time, as understood by git --since
+    pub feed_window: String,
+    /// Largest challenge answer that is submitted, in bytes
+    pub max_answer_size_bytes: u64,
+    /// Whether `pet feed` accepts a challenge when there is no terminal to ask in
+    pub challenge_prompt_default: ChallengePromptDefault,
+    /// Challenges that were accepted and not answered correctly yet. Like the feeding state
+    /// below, it belongs to the active profile and is saved in `profiles`.
+    #[serde(skip_serializing)]
+    pub challenges: Vec<Challenge>,
+    /// Older configs held a single challenge, which is moved into `challenges` on load
+    #[serde(skip_serializing)]
+    challenge: Option<Challenge>,
+    /// The pet and status animation from the last successful `pet status`, shown when offline
+    pub cached_pet: Option<Pet>,
+    pub cached_status_animation: Option<Animation>,
     pub last_update_check_time_ms: u128,
+    /// How often to check for a new version of BitPet. 0 never checks, as does setting the
+    /// BITPET_NO_UPDATE_CHECK env var to 1.
+    pub update_check_interval_hours: u64,
+    /// When the pet was last fed successfully, used as the default start of the commit window
+    #[serde(skip_serializing)]
+    pub last_feed_time_ms: Option<u128>,
+    /// Time of the newest commit fed from each repo, keyed by the SHA256 of the repo path, so
+    /// that no commit is counted twice
+    #[serde(skip_serializing)]
+    pub last_fed_commit_time_ms: HashMap<String, u64>,
+    /// The pet's stats over time, oldest first, for `pet history`
+    #[serde(skip_serializing)]
+    pub stats_history: Vec<StatSnapshot>,
+    /// Whether the user was told that their token could not be stored in the OS keychain
+    pub keychain_warning_shown: bool,
+    /// Where this config was loaded from and is saved to
+    #[serde(skip)]
+    store: Arc<dyn ConfigStore>,
+    /// The tokens known to be in the keychain, by keychain account: those read from it or
+    /// stored in it since the config was loaded. Every command saves the config, and writing to
+    /// the keychain can ask the user for permission on some platforms, so only a new or changed
+    /// token is written. Shared with the clones that `save` makes.
+    #[serde(skip)]
+    keychain_tokens: Arc<Mutex<BTreeMap<String, String>>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            user: None,
+            repos: Vec::new(),
+            profiles: BTreeMap::new(),
+            active_profile: DEFAULT_PROFILE.to_string(),
+            extra_author_emails: Vec::new(),
+            require_signed: false,
+            animation_speed: 1.0,
+            theme_path: None,
+            event_log_path: None,
+            network_retries: utils::DEFAULT_NETWORK_RETRIES,
+            request_timeout_ms: utils::DEFAULT_REQUEST_TIMEOUT_MS,
+            api_base_url: None,
+            feed_window: DEFAULT_FEED_WINDOW.to_string(),
+            max_answer_size_bytes: DEFAULT_MAX_ANSWER_SIZE_BYTES,
+            challenge_prompt_default: ChallengePromptDefault::default(),
+            challenges: Vec::new(),
+            challenge: None,
+            cached_pet: None,
+            cached_status_animation: None,
+            last_update_check_time_ms: 0,
+            update_check_interval_hours: DEFAULT_UPDATE_CHECK_INTERVAL_HOURS,
+            last_feed_time_ms: None,
+            last_fed_commit_time_ms: HashMap::new(),
+            stats_history: Vec::new(),
+            keychain_warning_shown: false,
+            store: Arc::new(FileStore {
+                path: PathBuf::new(),
+            }),
+            keychain_tokens: Arc::default(),
+        }
+    }
+}
+
+/// What to do when the pet asks for a challenge and nobody can be asked
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChallengePromptDefault {
+    Accept,
+    #[default]
+    Decline,
+}
+
+/// Name of the profile that older, single account configs are moved into
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// How far back to look for commits when neither --since nor a previous feed is available
+pub const DEFAULT_FEED_WINDOW: &str = "1week";
+
+pub const DEFAULT_MAX_ANSWER_SIZE_BYTES: u64 = 1024 * 1024;
+
+pub const DEFAULT_UPDATE_CHECK_INTERVAL_HOURS: u64 = 24;
+
+/// Settings that can be read and changed with `pet config`
+pub const SETTINGS: &[&str] = &[
+    "api_base_url",
+    "animation_speed",
+    "theme_path",
+    "event_log_path",
+    "network_retries",
+    "request_timeout_ms",
+    "feed_window",
+    "max_answer_size_bytes",
+    "challenge_prompt_default",
+    "require_signed",
+    "extra_author_emails",
+    "update_check_interval_hours",
+];
+
+/// Upper limit for network_retries, so that a typo cannot make a command hang for hours
+const MAX_NETWORK_RETRIES: u32 = 10;
+/// Upper limit for request_timeout_ms (10 minutes), for the same reason
+const MAX_REQUEST_TIMEOUT_MS: u64 = 10 * 60 * 1000;
+
+/// The pet's stats at one point in time
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct StatSnapshot {
+    pub time_ms: u128,
+    pub level: f64,
+    pub hunger: f64,
+    pub happiness: f64,
+    pub energy: f64,
+}
+
+/// Number of snapshots kept in stats_history, so that the config does not grow forever
+const MAX_STATS_HISTORY: usize = 1000;
+
+/// One BitPet account, the repos fed to its pet and what is known about feeding it
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct Profile {
+    pub user: Option<UserInfo>,
+    pub repos: Vec<String>,
+    pub challenges: Vec<Challenge>,
+    pub last_feed_time_ms: Option<u128>,
+    pub last_fed_commit_time_ms: HashMap<String, u64>,
+    pub stats_history: Vec<StatSnapshot>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
@@ -22,96 +194,813 @@ pub struct UserInfo {
     pub username: String,
     pub email: String,
     pub token: String,
+    /// Used to get a new token once `token` expires, if the server issued one
+    pub refresh_token: Option<String>,
+    pub token_expires_at_ms: Option<u128>,
 }
 
-impl Config {
-    /// Get the path to the config file
+impl UserInfo {
+    /// Whether the token is known to have expired, so that it is refreshed before it is used
+    pub fn token_expired(&self) -> bool {
+        let now_ms = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        self.token_expires_at_ms
+            .is_some_and(|expires_at_ms| expires_at_ms <= now_ms)
+    }
+}
 
-    pub fn config_path() -> Result<PathBuf, ConfigError> {
-        let config_dir = dirs::config_dir()
-            .ok_or(ConfigError::NoConfigDir(
-                std::backtrace::Backtrace::capture().to_string(),
-            ))?
-            .join("bitpet");
+impl Config {
+    /// Get the path to the config file, from the first of:
+    /// 1. an explicit path (from --config)
+    /// 2. the BITPET_CONFIG env var (the full path of the file)
+    /// 3. `$XDG_CONFIG_HOME/bitpet/config.json`, on every platform
+    /// 4. `bitpet/config.json` in the platform's config dir (e.g. `~/.config` on Linux and
+    ///    `~/Library/Application Support` on macOS)
+    pub fn config_path(path_override: Option<PathBuf>) -> Result<PathBuf, ConfigError> {
+        let env_path = std::env::var_os("BITPET_CONFIG")
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        let config_path = match path_override.or(env_path) {
+            Some(path) => path,
+            None => {
+                // The XDG spec says relative paths are to be ignored
+                let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME")
+                    .map(PathBuf::from)
+                    .filter(|path| path.is_absolute());
+                xdg_config_home
+                    .or_else(dirs::config_dir)
+                    .ok_or(ConfigError::NoConfigDir(error::capture_backtrace()))?
+                    .join("bitpet")
+                    .join("config.json")
+            }
+        };
 
         // Ensure the directory exists
-        fs::create_dir_all(&config_dir).map_err(|e| {
-            ConfigError::IoError(e, std::backtrace::Backtrace::capture().to_string())
-        })?;
+        if let Some(config_dir) = config_path.parent()
+            && !config_dir.as_os_str().is_empty()
+        {
+            fs::create_dir_all(config_dir)
+                .map_err(|e| ConfigError::IoError(e, error::capture_backtrace()))?;
+        }
 
-        Ok(config_dir.join("config.json"))
+        Ok(config_path)
     }
 
     /// Load config from file, creating a default one if it doesn't exist
+    pub fn load(path_override: Option<PathBuf>) -> Result<Config, ConfigError> {
+        let path = Self::config_path(path_override)?;
+        Self::load_from(Arc::new(FileStore { path }))
+    }
 
-    pub fn load() -> Result<Config, ConfigError> {
-        let config_path = Self::config_path()?;
+    /// A config that is only kept in memory, so that command logic can be run without touching
+    /// the user's config file or keychain
+    #[cfg(test)]
+    pub fn in_memory() -> Config {
+        Config {
+            store: Arc::new(MemoryStore::default()),
+            ..Config::default()
+        }
+    }
 
-        if !config_path.exists() {
+    /// Load config from a store, creating a default one if it has none
+    pub fn load_from(store: Arc<dyn ConfigStore>) -> Result<Config, ConfigError> {
+        let content = store
+            .read()
+            .map_err(|e| ConfigError::IoError(e, error::capture_backtrace()))?;
+        let Some(content) = content else {
             // Create default config and save it
-            let default_config = Config::default();
+            let default_config = Config {
+                store,
+                ..Config::default()
+            };
             default_config.save()?;
             return Ok(default_config);
-        }
+        };
 
-        let content = fs::read_to_string(&config_path).map_err(|e| {
-            ConfigError::IoError(e, std::backtrace::Backtrace::capture().to_string())
-        })?;
+        let mut config: Config = match serde_json::from_str(&content) {
+            Ok(config) => config,
+            Err(e) if std::env::var("BITPET_NO_CONFIG_RECOVERY").as_deref() == Ok("1") => {
+                return Err(ConfigError::ParseError(e, error::capture_backtrace()));
+            }
+            Err(e) => {
+                // Move the unreadable config aside so the user can inspect it, and start over
+                let corrupt_path = store
+                    .move_aside()
+                    .map_err(|e| ConfigError::IoError(e, error::capture_backtrace()))?;
+                eprintln!(
+                    "{}",
+                    format!(
+                        "Warning: Your config file could not be parsed ({}). It was moved to {} and a fresh config was created.",
+                        e,
+                        corrupt_path.display()
+                    )
+                    .yellow()
+                );
 
-        let config: Config = serde_json::from_str(&content).map_err(|e| {
-            ConfigError::ParseError(e, std::backtrace::Backtrace::capture().to_string())
-        })?;
+                let default_config = Config {
+                    store: store.clone(),
+                    ..Config::default()
+                };
+                default_config.save()?;
+                default_config
+            }
+        };
+        config.store = store;
+        if let Some(challenge) = config.challenge.take() {
+            config.add_challenge(challenge);
+        }
+
+        match config.profiles.remove(&config.active_profile) {
+            Some(profile) => config.activate_profile(profile),
+            // Older configs hold a single account, which becomes the default profile
+            None if config.profiles.is_empty() => {
+                config.active_profile = DEFAULT_PROFILE.to_string();
+                config.save()?;
+            }
+            None => {
+                return Err(ConfigError::UnknownProfile(
+                    config.active_profile.clone(),
+                    error::capture_backtrace(),
+                ));
+            }
+        }
+        config.load_token()?;
 
         Ok(config)
     }
 
-    /// Save config to file
+    /// Replace the keychain placeholder in the active profile's token with the real token, or
+    /// move a plain text token into the keychain
+    fn load_token(&mut self) -> Result<(), ConfigError> {
+        let Some(mut user) = self.user.take() else {
+            return Ok(());
+        };
+        if user.refresh_token.as_deref() == Some(KEYCHAIN_TOKEN_PLACEHOLDER) {
+            // Without it the token cannot be refreshed, but it can still be used until it expires
+            user.refresh_token = self
+                .read_keychain_token(&refresh_token_account(&user.email))
+                .ok();
+        }
+        if user.token == KEYCHAIN_TOKEN_PLACEHOLDER {
+            match self.read_keychain_token(&user.email) {
+                Ok(token) => {
+                    user.token = token;
+                    self.user = Some(user);
+                }
+                Err(e) => {
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "Warning: Could not read your login token from the OS keychain ({}). Please login again.",
+                            e
+                        )
+                        .yellow()
+                    );
+                    self.save()?;
+                }
+            }
+        } else if !self.keychain_warning_shown {
+            // Moves a token from an older, plain text config into the keychain
+            let stored = self.write_keychain_token(&user.email, &user.token);
+            self.user = Some(user);
+            match stored {
+                Ok(()) => self.save()?,
+                Err(e) => {
+                    eprintln!(
+                        "{}",
+                        format!(
+                            "Warning: Could not access the OS keychain ({}). Your login token will be stored in plain text in {}.",
+                            e,
+                            self.path().display()
+                        )
+                        .yellow()
+                    );
+                    self.keychain_warning_shown = true;
+                    self.save()?;
+                }
+            }
+        } else {
+            self.user = Some(user);
+        }
+        Ok(())
+    }
 
+    /// Save config to file. The login token is kept in the OS keychain when possible, with only
+    /// a placeholder written to the file.
     pub fn save(&self) -> Result<(), ConfigError> {
-        let config_path = Self::config_path()?;
+        let mut config = self.clone();
+        let active_profile = config.take_active_profile();
+        config
+            .profiles
+            .insert(config.active_profile.clone(), active_profile);
+        // Inactive profiles hold tokens too, read from the keychain while they were active
+        for user in config.profiles.values_mut().filter_map(|p| p.user.as_mut()) {
+            self.hide_tokens(user);
+        }
 
-        let content = serde_json::to_string_pretty(self).map_err(|e| {
-            ConfigError::SerializeError(e, std::backtrace::Backtrace::capture().to_string())
-        })?;
+        let content = serde_json::to_string_pretty(&config)
+            .map_err(|e| ConfigError::SerializeError(e, error::capture_backtrace()))?;
 
-        fs::write(&config_path, content).map_err(|e| {
-            ConfigError::IoError(e, std::backtrace::Backtrace::capture().to_string())
-        })?;
+        self.store
+            .write(&content)
+            .map_err(|e| ConfigError::IoError(e, error::capture_backtrace()))?;
 
         Ok(())
     }
 
-    pub fn get_valid_normalised_paths_and_save(
-        &mut self,
-    ) -> Result<Vec<utils::NormalisedGitPath>, ConfigError> {
-        let mut valid_repos = Vec::new();
-        let mut valid_paths = Vec::new();
+    /// Move the tokens of a user into the keychain, replacing them with a placeholder. A token
+    /// that cannot be stored there is kept as it is.
+    fn hide_tokens(&self, user: &mut UserInfo) {
+        if user.token != KEYCHAIN_TOKEN_PLACEHOLDER
+            && self.write_keychain_token(&user.email, &user.token).is_ok()
+        {
+            user.token = KEYCHAIN_TOKEN_PLACEHOLDER.to_string();
+        }
+        if let Some(refresh_token) = user.refresh_token.as_mut()
+            && refresh_token != KEYCHAIN_TOKEN_PLACEHOLDER
+            && self
+                .write_keychain_token(&refresh_token_account(&user.email), refresh_token)
+                .is_ok()
+        {
+            *refresh_token = KEYCHAIN_TOKEN_PLACEHOLDER.to_string();
+        }
+    }
+
+    /// Add a pending challenge, unless one with the same ID is already pending
+    pub fn add_challenge(&mut self, challenge: Challenge) {
+        if !self.challenges.iter().any(|c| c.id == challenge.id) {
+            self.challenges.push(challenge);
+        }
+    }
+
+    /// Remove the pending challenge with the given ID, returning whether there was one
+    pub fn remove_challenge(&mut self, id: &str) -> bool {
+        let count = self.challenges.len();
+        self.challenges.retain(|challenge| challenge.id != id);
+        self.challenges.len() != count
+    }
+
+    /// Add the pet's current stats to stats_history, dropping the oldest ones past the limit.
+    /// The config is not saved.
+    pub fn record_stats(&mut self, pet: &Pet) {
+        self.stats_history.push(StatSnapshot {
+            time_ms: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis(),
+            level: pet.level,
+            hunger: pet.hunger,
+            happiness: pet.happiness,
+            energy: pet.energy,
+        });
+        if self.stats_history.len() > MAX_STATS_HISTORY {
+            let excess = self.stats_history.len() - MAX_STATS_HISTORY;
+            self.stats_history.drain(..excess);
+        }
+    }
+
+    /// Add an empty profile, returning false if there already is one with this name. The config
+    /// is not saved.
+    pub fn add_profile(&mut self, name: &str) -> bool {
+        if name == self.active_profile || self.profiles.contains_key(name) {
+            return false;
+        }
+        self.profiles.insert(name.to_string(), Profile::default());
+        true
+    }
+
+    /// Make another profile the active one. The pet cached for offline use belongs to the
+    /// previous account, so it is dropped. The config is not saved.
+    pub fn use_profile(&mut self, name: &str) -> Result<(), ConfigError> {
+        if name == self.active_profile {
+            return Ok(());
+        }
+        let profile = self
+            .profiles
+            .remove(name)
+            .ok_or(ConfigError::UnknownProfile(
+                name.to_string(),
+                error::capture_backtrace(),
+            ))?;
+        let previous = self.take_active_profile();
+        self.profiles.insert(
+            std::mem::replace(&mut self.active_profile, name.to_string()),
+            previous,
+        );
+        self.activate_profile(profile);
+        self.cached_pet = None;
+        self.cached_status_animation = None;
+        self.load_token()
+    }
+
+    /// Take the active profile out of the fields it is kept in while active, leaving them empty
+    fn take_active_profile(&mut self) -> Profile {
+        Profile {
+            user: self.user.take(),
+            repos: std::mem::take(&mut self.repos),
+            challenges: std::mem::take(&mut self.challenges),
+            last_feed_time_ms: self.last_feed_time_ms.take(),
+            last_fed_commit_time_ms: std::mem::take(&mut self.last_fed_commit_time_ms),
+            stats_history: std::mem::take(&mut self.stats_history),
+        }
+    }
+
+    /// Put a profile into the fields it is kept in while active. Configs from before profiles
+    /// held the feeding state have it at the top level, where it is merged with the profile's.
+    fn activate_profile(&mut self, profile: Profile) {
+        self.user = profile.user;
+        self.repos = profile.repos;
+        for challenge in profile.challenges {
+            self.add_challenge(challenge);
+        }
+        self.last_feed_time_ms = profile.last_feed_time_ms.or(self.last_feed_time_ms);
+        self.last_fed_commit_time_ms
+            .extend(profile.last_fed_commit_time_ms);
+        if !profile.stats_history.is_empty() {
+            self.stats_history = profile.stats_history;
+        }
+    }
+
+    /// Remove a profile other than the active one, along with its token in the keychain. The
+    /// config is not saved.
+    pub fn remove_profile(&mut self, name: &str) -> Result<(), ConfigError> {
+        if name == self.active_profile {
+            return Err(ConfigError::ActiveProfile(
+                name.to_string(),
+                error::capture_backtrace(),
+            ));
+        }
+        let profile = self
+            .profiles
+            .remove(name)
+            .ok_or(ConfigError::UnknownProfile(
+                name.to_string(),
+                error::capture_backtrace(),
+            ))?;
+        if let Some(user) = profile.user {
+            let email_in_use = self
+                .user
+                .iter()
+                .chain(self.profiles.values().flat_map(|p| &p.user))
+                .any(|u| u.email == user.email);
+            if !email_in_use {
+                self.delete_keychain_tokens(&user.email);
+            }
+        }
+        Ok(())
+    }
+
+    fn keychain_tokens(&self) -> MutexGuard<'_, BTreeMap<String, String>> {
+        self.keychain_tokens
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Store a token in the keychain, unless it is known to be there already
+    fn write_keychain_token(&self, account: &str, token: &str) -> keyring::Result<()> {
+        if self
+            .keychain_tokens()
+            .get(account)
+            .is_some_and(|stored| stored == token)
+        {
+            return Ok(());
+        }
+        self.store.write_token(account, token)?;
+        self.keychain_tokens()
+            .insert(account.to_string(), token.to_string());
+        Ok(())
+    }
+
+    fn read_keychain_token(&self, account: &str) -> keyring::Result<String> {
+        let token = self.store.read_token(account)?;
+        self.keychain_tokens()
+            .insert(account.to_string(), token.clone());
+        Ok(token)
+    }
+
+    /// Remove the login and refresh tokens of the given account from the keychain, if they are
+    /// there
+    pub fn delete_keychain_tokens(&self, email: &str) {
+        for account in [email.to_string(), refresh_token_account(email)] {
+            self.keychain_tokens().remove(&account);
+            self.store.delete_token(&account);
+        }
+    }
+
+    /// Where this config was loaded from and is saved to
+    pub fn path(&self) -> &Path {
+        self.store.path()
+    }
+
+    /// The value of one of the `SETTINGS`, as text
+    pub fn get_setting(&self, key: &str) -> Result<String, ConfigError> {
+        let value = match key {
+            "api_base_url" => self.api_base_url.clone().unwrap_or_default(),
+            "animation_speed" => self.animation_speed.to_string(),
+            "theme_path" => self
+                .theme_path
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            "event_log_path" => self
+                .event_log_path
+                .as_ref()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default(),
+            "network_retries" => self.network_retries.to_string(),
+            "request_timeout_ms" => self.request_timeout_ms.to_string(),
+            "feed_window" => self.feed_window.clone(),
+            "max_answer_size_bytes" => self.max_answer_size_bytes.to_string(),
+            "challenge_prompt_default" => match self.challenge_prompt_default {
+                ChallengePromptDefault::Accept => "accept".to_string(),
+                ChallengePromptDefault::Decline => "decline".to_string(),
+            },
+            "require_signed" => self.require_signed.to_string(),
+            "extra_author_emails" => self.extra_author_emails.join(","),
+            "update_check_interval_hours" => self.update_check_interval_hours.to_string(),
+            _ => {
+                return Err(ConfigError::UnknownSetting(
+                    key.to_string(),
+                    error::capture_backtrace(),
+                ));
+            }
+        };
+        Ok(value)
+    }
+
+    /// Validate and change one of the `SETTINGS`. An empty value resets api_base_url,
+    /// theme_path, event_log_path and extra_author_emails. The config is not saved.
+    pub fn set_setting(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let value = value.trim();
+        let invalid = |message: &str| {
+            ConfigError::InvalidSetting(
+                key.to_string(),
+                message.to_string(),
+                error::capture_backtrace(),
+            )
+        };
+        match key {
+            "api_base_url" => {
+                if value.is_empty() {
+                    self.api_base_url = None;
+                } else if value.starts_with("http://") || value.starts_with("https://") {
+                    self.api_base_url = Some(value.trim_end_matches('/').to_string());
+                } else {
+                    return Err(invalid("it must start with http:// or https://"));
+                }
+            }
+            "animation_speed" => {
+                let speed: f32 = value
+                    .parse()
+                    .map_err(|_| invalid("it must be a number, like 1.5"))?;
+                if !(ui::MIN_ANIMATION_SPEED..=ui::MAX_ANIMATION_SPEED).contains(&speed) {
+                    return Err(invalid(&format!(
+                        "it must be between {} and {}",
+                        ui::MIN_ANIMATION_SPEED,
+                        ui::MAX_ANIMATION_SPEED
+                    )));
+                }
+                self.animation_speed = speed;
+            }
+            "theme_path" => {
+                if value.is_empty() {
+                    self.theme_path = None;
+                } else {
+                    // Stored as an absolute path, since commands run from any directory
+                    match fs::canonicalize(value) {
+                        Ok(path) if path.is_file() => self.theme_path = Some(path),
+                        _ => return Err(invalid("it must be the path of a theme file")),
+                    }
+                }
+            }
+            "event_log_path" => {
+                if value.is_empty() {
+                    self.event_log_path = None;
+                } else {
+                    // The file is created by the first event, but its directory must exist
+                    let path = std::path::absolute(value)
+                        .map_err(|_| invalid("it must be the path of a file"))?;
+                    if path.is_dir() || !path.parent().is_some_and(Path::is_dir) {
+                        return Err(invalid(
+                            "it must be the path of a file, in a directory that exists",
+                        ));
+                    }
+                    self.event_log_path = Some(path);
+                }
+            }
+            "network_retries" => {
+                let retries: u32 = value
+                    .parse()
+                    .map_err(|_| invalid("it must be a whole number"))?;
+                if retries > MAX_NETWORK_RETRIES {
+                    return Err(invalid(&format!(
+                        "it must be at most {}",
+                        MAX_NETWORK_RETRIES
+                    )));
+                }
+                self.network_retries = retries;
+            }
+            "request_timeout_ms" => {
+                let timeout_ms: u64 = value
+                    .parse()
+                    .map_err(|_| invalid("it must be a whole number of milliseconds"))?;
+                if !(1..=MAX_REQUEST_TIMEOUT_MS).contains(&timeout_ms) {
+                    return Err(invalid(&format!(
+                        "it must be between 1 and {}",
+                        MAX_REQUEST_TIMEOUT_MS
+                    )));
+                }
+                self.request_timeout_ms = timeout_ms;
+            }
+            "feed_window" => {
+                if value.is_empty() {
+                    return Err(invalid("it must not be empty, e.g. 1week or 3days"));
+                }
+                self.feed_window = value.to_string();
+            }
+            "max_answer_size_bytes" => {
+                let size: u64 = value
+                    .parse()
+                    .map_err(|_| invalid("it must be a whole number of bytes"))?;
+                if size == 0 {
+                    return Err(invalid("it must be more than 0"));
+                }
+                self.max_answer_size_bytes = size;
+            }
+            "challenge_prompt_default" => {
+                self.challenge_prompt_default = match value.to_lowercase().as_str() {
+                    "accept" => ChallengePromptDefault::Accept,
+                    "decline" => ChallengePromptDefault::Decline,
+                    _ => return Err(invalid("it must be accept or decline")),
+                };
+            }
+            "require_signed" => {
+                self.require_signed = value
+                    .parse()
+                    .map_err(|_| invalid("it must be true or false"))?;
+            }
+            "extra_author_emails" => {
+                let emails: Vec<String> = value
+                    .split(',')
+                    .map(|email| email.trim().to_string())
+                    .filter(|email| !email.is_empty())
+                    .collect();
+                if let Some(email) = emails.iter().find(|email| !email.contains('@')) {
+                    return Err(invalid(&format!("'{}' is not an email address", email)));
+                }
+                self.extra_author_emails = emails;
+            }
+            "update_check_interval_hours" => {
+                self.update_check_interval_hours = value
+                    .parse()
+                    .map_err(|_| invalid("it must be a whole number of hours (0 never checks)"))?;
+            }
+            _ => {
+                return Err(ConfigError::UnknownSetting(
+                    key.to_string(),
+                    error::capture_backtrace(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a repo unless it was added already, returning whether it was added. The config is not
+    /// saved.
+    pub fn add_repo(&mut self, normalised_path: &utils::NormalisedGitPath) -> bool {
+        // Compare canonical forms so that the same repo added via a different path (a symlink, a
+        // subdirectory or a trailing slash) is detected
+        let already_added =
+            self.repos
+                .iter()
+                .any(|repo| match utils::NormalisedGitPath::new(repo.clone()) {
+                    Ok(existing_path) => existing_path == *normalised_path,
+                    Err(_) => repo.trim_end_matches('/') == normalised_path.to_string(),
+                });
+        if already_added {
+            return false;
+        }
+        self.repos.push(normalised_path.to_string());
+        true
+    }
 
+    /// The entry of `repos` that `path` names, if any. A repo that was deleted or moved cannot
+    /// be normalised, so it is looked up by the path it was added as instead.
+    pub fn find_repo(&self, path: String) -> Result<Option<String>, utils::NormalisedPathError> {
+        let repo_path = match utils::NormalisedGitPath::new(path.clone()) {
+            Ok(normalised_path) => normalised_path.to_string(),
+            Err(utils::NormalisedPathError::PathNotGitRepository(path, _, _)) => {
+                path.trim_end_matches('/').to_string()
+            }
+            Err(e) => {
+                let path = path.trim().trim_end_matches('/');
+                let absolute_path = std::path::absolute(path)
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default();
+                return match self.repos.iter().find(|repo| {
+                    let repo = repo.trim_end_matches('/');
+                    repo == path || repo == absolute_path
+                }) {
+                    Some(repo) => Ok(Some(repo.clone())),
+                    None => Err(e),
+                };
+            }
+        };
+        Ok(self.repos.contains(&repo_path).then_some(repo_path))
+    }
+
+    /// The repos that are git repos right now (without duplicates, e.g. via a symlink), and
+    /// those that are not, e.g. because they were deleted or are on a drive that is not
+    /// mounted. Nothing is removed; that is left to `prune_repos`.
+    pub fn get_normalised_paths(&self) -> (Vec<utils::NormalisedGitPath>, Vec<String>) {
+        let mut valid_paths = Vec::new();
+        let mut unavailable_repos = Vec::new();
         for repo in &self.repos {
             match utils::NormalisedGitPath::new(repo.clone()) {
                 Ok(normalised_path) => {
-                    valid_repos.push(repo.clone());
-                    valid_paths.push(normalised_path);
-                }
-                Err(_) => {
-                    // Skip invalid repositories
+                    if !valid_paths.contains(&normalised_path) {
+                        valid_paths.push(normalised_path);
+                    }
                 }
+                Err(_) => unavailable_repos.push(repo.clone()),
             }
         }
+        (valid_paths, unavailable_repos)
+    }
+
+    /// Remove the repos that are not available, returning them. The config is not saved.
+    pub fn prune_repos(&mut self) -> Vec<String> {
+        let (_, unavailable_repos) = self.get_normalised_paths();
+        self.repos.retain(|repo| !unavailable_repos.contains(repo));
+        unavailable_repos
+    }
+}
+
+const KEYCHAIN_SERVICE: &str = "bitpet";
+const KEYCHAIN_TOKEN_PLACEHOLDER: &str = "<stored in OS keychain>";
+
+/// Keychain entries are named after the account's email, so the refresh token gets its own name
+fn refresh_token_account(email: &str) -> String {
+    format!("{} (refresh token)", email)
+}
+
+/// Where a config is read from and saved to
+pub trait ConfigStore: std::fmt::Debug + Send + Sync {
+    /// The saved config, or None if none was saved yet
+    fn read(&self) -> std::io::Result<Option<String>>;
+    fn write(&self, content: &str) -> std::io::Result<()>;
+    /// Keep a config that could not be parsed where the user can inspect it, and return where
+    fn move_aside(&self) -> std::io::Result<PathBuf>;
+    /// Shown to the user as the location of the config
+    fn path(&self) -> &Path;
+    /// Keep a login token in the OS keychain, under the given keychain account
+    fn write_token(&self, account: &str, token: &str) -> keyring::Result<()> {
+        keyring::Entry::new(KEYCHAIN_SERVICE, account)?.set_password(token)
+    }
+    fn read_token(&self, account: &str) -> keyring::Result<String> {
+        keyring::Entry::new(KEYCHAIN_SERVICE, account)?.get_password()
+    }
+    /// Remove a token from the OS keychain, if it is there
+    fn delete_token(&self, account: &str) {
+        if let Ok(entry) = keyring::Entry::new(KEYCHAIN_SERVICE, account) {
+            let _ = entry.delete_credential();
+        }
+    }
+}
+
+/// The config file, at the path from `Config::config_path`
+#[derive(Debug)]
+pub struct FileStore {
+    pub path: PathBuf,
+}
+
+impl ConfigStore for FileStore {
+    fn read(&self) -> std::io::Result<Option<String>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        fs::read_to_string(&self.path).map(Some)
+    }
+
+    fn write(&self, content: &str) -> std::io::Result<()> {
+        write_atomically(&self.path, content.as_bytes())
+    }
+
+    fn move_aside(&self) -> std::io::Result<PathBuf> {
+        let corrupt_path = self.path.with_file_name(format!(
+            "{}.corrupt-{}",
+            self.path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            chrono::Local::now().format("%Y%m%d%H%M%S")
+        ));
+        fs::rename(&self.path, &corrupt_path)?;
+        Ok(corrupt_path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// A config kept in memory only, see `Config::in_memory`
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    content: Mutex<Option<String>>,
+    path: PathBuf,
+    /// Stands in for the OS keychain
+    tokens: Mutex<BTreeMap<String, String>>,
+    /// How many times a token was written to `tokens`
+    token_writes: Mutex<usize>,
+}
+
+#[cfg(test)]
+impl ConfigStore for MemoryStore {
+    fn read(&self) -> std::io::Result<Option<String>> {
+        Ok(self.content.lock().unwrap().clone())
+    }
+
+    fn write(&self, content: &str) -> std::io::Result<()> {
+        *self.content.lock().unwrap() = Some(content.to_string());
+        Ok(())
+    }
+
+    fn move_aside(&self) -> std::io::Result<PathBuf> {
+        self.content.lock().unwrap().take();
+        Ok(self.path.clone())
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_token(&self, account: &str, token: &str) -> keyring::Result<()> {
+        *self.token_writes.lock().unwrap() += 1;
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(account.to_string(), token.to_string());
+        Ok(())
+    }
+
+    fn read_token(&self, account: &str) -> keyring::Result<String> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .get(account)
+            .cloned()
+            .ok_or(keyring::Error::NoEntry)
+    }
+
+    fn delete_token(&self, account: &str) {
+        self.tokens.lock().unwrap().remove(account);
+    }
+}
 
-        self.repos = valid_repos;
-        self.save()?;
+/// Write to a temporary file next to `path` and rename it over `path`, so that a crash
+/// mid-write never leaves a truncated file behind (rename is atomic on the same filesystem)
+fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    write_atomically_with(path, |file| file.write_all(content))
+}
+
+/// `write_atomically`, with the writing of the content to the temporary file left to `write`
+fn write_atomically_with(
+    path: &Path,
+    write: impl FnOnce(&mut fs::File) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
 
-        Ok(valid_paths)
+    let result = (|| {
+        let mut file = fs::File::create(&tmp_path)?;
+        write(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
     }
+    result
 }
 
 #[derive(Debug)]
 pub enum ConfigError {
-    NoConfigDir(String),
-    IoError(std::io::Error, String),
-    ParseError(serde_json::Error, String),
-    SerializeError(serde_json::Error, String),
+    NoConfigDir(Vec<error::Frame>),
+    IoError(std::io::Error, Vec<error::Frame>),
+    ParseError(serde_json::Error, Vec<error::Frame>),
+    SerializeError(serde_json::Error, Vec<error::Frame>),
+    UnknownSetting(String, Vec<error::Frame>),
+    InvalidSetting(String, String, Vec<error::Frame>),
+    UnknownProfile(String, Vec<error::Frame>),
+    ActiveProfile(String, Vec<error::Frame>),
 }
 
 impl std::fmt::Display for ConfigError {
@@ -121,27 +1010,387 @@ impl std::fmt::Display for ConfigError {
             ConfigError::IoError(e, _) => write!(f, "IO error: {}", e),
             ConfigError::ParseError(e, _) => write!(f, "Failed to parse config: {}", e),
             ConfigError::SerializeError(e, _) => write!(f, "Failed to serialize config: {}", e),
+            ConfigError::UnknownSetting(key, _) => write!(
+                f,
+                "Unknown setting '{}'. Valid settings are: {}",
+                key,
+                SETTINGS.join(", ")
+            ),
+            ConfigError::InvalidSetting(key, message, _) => {
+                write!(f, "Invalid value for {}: {}", key, message)
+            }
+            ConfigError::UnknownProfile(name, _) => write!(
+                f,
+                "There is no profile named '{}'. Run 'pet account list' to see your profiles.",
+                name
+            ),
+            ConfigError::ActiveProfile(name, _) => write!(
+                f,
+                "'{}' is the active profile. Switch to another one with 'pet account use' first.",
+                name
+            ),
         }
     }
 }
 
 impl std::error::Error for ConfigError {}
 
-impl error::WithBacktrace for ConfigError {
-    fn backtrace(&self) -> &String {
-        match self {
-            ConfigError::NoConfigDir(s)
-            | ConfigError::IoError(_, s)
-            | ConfigError::ParseError(_, s)
-            | ConfigError::SerializeError(_, s) => s,
+error::impl_custom_error!(
+    ConfigError {
+        NoConfigDir(),
+        IoError(_),
+        ParseError(_),
+        SerializeError(_),
+        UnknownSetting(_),
+        InvalidSetting(_, _),
+        UnknownProfile(_),
+        ActiveProfile(_),
+    },
+    exit_code = error::EXIT_CODE_CONFIG
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A store holding a config saved by an older version
+    fn memory_store_with(content: &str) -> Arc<MemoryStore> {
+        Arc::new(MemoryStore {
+            content: Mutex::new(Some(content.to_string())),
+            ..MemoryStore::default()
+        })
+    }
+
+    fn saved_json(store: &MemoryStore) -> serde_json::Value {
+        serde_json::from_str(store.content.lock().unwrap().as_deref().unwrap()).unwrap()
+    }
+
+    fn challenge(id: &str) -> Challenge {
+        Challenge {
+            id: id.to_string(),
+            description: format!("Challenge {}", id),
+            ..Challenge::default()
         }
     }
-}
 
-impl error::CustomErrorTrait for ConfigError {}
+    #[test]
+    fn a_saved_config_loads_the_same() {
+        let mut config = Config::in_memory();
+        config.user = Some(UserInfo {
+            username: "bit".to_string(),
+            email: "bit@example.com".to_string(),
+            token: "token".to_string(),
+            refresh_token: Some("refresh".to_string()),
+            token_expires_at_ms: Some(1234),
+        });
+        config.repos = vec!["/repos/bitpet".to_string()];
+        config.feed_window = "3days".to_string();
+        config.add_challenge(challenge("1"));
+        config
+            .last_fed_commit_time_ms
+            .insert("repo".to_string(), 42);
+        config.save().unwrap();
+
+        let loaded = Config::load_from(config.store.clone()).unwrap();
+        let user = loaded.user.unwrap();
+        assert_eq!(user.username, "bit");
+        // Read back from the keychain
+        assert_eq!(user.token, "token");
+        assert_eq!(user.refresh_token.as_deref(), Some("refresh"));
+        assert_eq!(user.token_expires_at_ms, Some(1234));
+        assert_eq!(loaded.repos, config.repos);
+        assert_eq!(loaded.feed_window, "3days");
+        assert_eq!(loaded.challenges.len(), 1);
+        assert_eq!(
+            loaded.last_fed_commit_time_ms,
+            config.last_fed_commit_time_ms
+        );
+        assert_eq!(loaded.active_profile, DEFAULT_PROFILE);
+        assert!(loaded.profiles.is_empty());
+    }
+
+    #[test]
+    fn a_single_account_config_becomes_the_default_profile() {
+        let store = memory_store_with(
+            r#"{
+                "user": {"username": "bit", "email": "bit@example.com", "token": "token"},
+                "repos": ["/repos/bitpet"]
+            }"#,
+        );
+
+        let config = Config::load_from(store.clone()).unwrap();
+        assert_eq!(config.active_profile, DEFAULT_PROFILE);
+        assert_eq!(config.user.as_ref().unwrap().username, "bit");
+        assert_eq!(config.repos, ["/repos/bitpet"]);
+
+        // Saved right away, with the account moved out of the top level
+        let saved = saved_json(&store);
+        assert_eq!(
+            saved["profiles"][DEFAULT_PROFILE]["user"]["username"],
+            "bit"
+        );
+        assert_eq!(
+            saved["profiles"][DEFAULT_PROFILE]["repos"][0],
+            "/repos/bitpet"
+        );
+        assert!(saved.get("user").is_none());
+        assert!(saved.get("repos").is_none());
+    }
+
+    #[test]
+    fn a_single_challenge_is_moved_into_challenges() {
+        let store = memory_store_with(
+            r#"{
+                "challenge": {"id": "2", "description": "Old", "answer_type": "File"},
+                "challenges": [{"id": "1", "description": "Pending"}]
+            }"#,
+        );
+        let config = Config::load_from(store.clone()).unwrap();
+        let ids: Vec<&str> = config.challenges.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["1", "2"]);
+        assert!(saved_json(&store).get("challenge").is_none());
+
+        // One that is also in challenges is not added twice
+        let store = memory_store_with(
+            r#"{"challenge": {"id": "1"}, "challenges": [{"id": "1", "description": "Pending"}]}"#,
+        );
+        let config = Config::load_from(store).unwrap();
+        assert_eq!(config.challenges.len(), 1);
+        assert_eq!(config.challenges[0].description, "Pending");
+    }
+
+    #[test]
+    fn each_profile_keeps_its_own_feeding_state() {
+        let mut config = Config::in_memory();
+        config.user = Some(UserInfo {
+            username: "home".to_string(),
+            ..UserInfo::default()
+        });
+        config.add_challenge(challenge("home"));
+        config.last_feed_time_ms = Some(1);
+        config.last_fed_commit_time_ms.insert("repo".to_string(), 1);
+        config.stats_history.push(StatSnapshot {
+            time_ms: 1,
+            ..StatSnapshot::default()
+        });
+
+        assert!(config.add_profile("work"));
+        config.use_profile("work").unwrap();
+        assert!(config.user.is_none());
+        assert!(config.challenges.is_empty());
+        assert_eq!(config.last_feed_time_ms, None);
+        assert!(config.last_fed_commit_time_ms.is_empty());
+        assert!(config.stats_history.is_empty());
+
+        config.add_challenge(challenge("work"));
+        config.last_feed_time_ms = Some(2);
+        config.save().unwrap();
+
+        // Switching back, also after a reload, brings back the first profile's state
+        let mut config = Config::load_from(config.store.clone()).unwrap();
+        assert_eq!(config.challenges[0].id, "work");
+        config.use_profile(DEFAULT_PROFILE).unwrap();
+        assert_eq!(config.user.as_ref().unwrap().username, "home");
+        assert_eq!(config.challenges.len(), 1);
+        assert_eq!(config.challenges[0].id, "home");
+        assert_eq!(config.last_feed_time_ms, Some(1));
+        assert_eq!(config.last_fed_commit_time_ms["repo"], 1);
+        assert_eq!(config.stats_history.len(), 1);
+
+        let work = &config.profiles["work"];
+        assert_eq!(work.challenges[0].id, "work");
+        assert_eq!(work.last_feed_time_ms, Some(2));
+    }
+
+    #[test]
+    fn switching_profiles_keeps_every_token_out_of_the_config() {
+        let store = Arc::new(MemoryStore::default());
+        let mut config = Config::load_from(store.clone()).unwrap();
+        config.user = Some(UserInfo {
+            refresh_token: Some("home-refresh".to_string()),
+            ..user("home", "home-token")
+        });
+        config.save().unwrap();
+
+        config.add_profile("work");
+        config.use_profile("work").unwrap();
+        config.user = Some(user("work", "work-token"));
+        config.save().unwrap();
+        config.use_profile(DEFAULT_PROFILE).unwrap();
+        config.save().unwrap();
+
+        let saved = store.content.lock().unwrap().clone().unwrap();
+        for token in ["home-token", "home-refresh", "work-token"] {
+            assert!(!saved.contains(token), "{} was saved: {}", token, saved);
+        }
+        let saved = saved_json(&store);
+        for profile in [DEFAULT_PROFILE, "work"] {
+            assert_eq!(
+                saved["profiles"][profile]["user"]["token"],
+                KEYCHAIN_TOKEN_PLACEHOLDER
+            );
+        }
+
+        // Both are read back from the keychain when their profile is used
+        assert_eq!(config.user.as_ref().unwrap().token, "home-token");
+        config.use_profile("work").unwrap();
+        assert_eq!(config.user.as_ref().unwrap().token, "work-token");
+    }
+
+    #[test]
+    fn feeding_state_from_before_profiles_held_it_is_kept() {
+        let store = memory_store_with(
+            r#"{
+                "profiles": {"default": {"challenges": [{"id": "2"}]}},
+                "active_profile": "default",
+                "challenges": [{"id": "1"}],
+                "last_feed_time_ms": 5
+            }"#,
+        );
+        let config = Config::load_from(store).unwrap();
+        let ids: Vec<&str> = config.challenges.iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(ids, ["1", "2"]);
+        assert_eq!(config.last_feed_time_ms, Some(5));
+    }
+
+    fn user(username: &str, token: &str) -> UserInfo {
+        UserInfo {
+            username: username.to_string(),
+            email: format!("{}@example.com", username),
+            token: token.to_string(),
+            ..UserInfo::default()
+        }
+    }
+
+    #[test]
+    fn a_token_is_only_written_to_the_keychain_when_it_changes() {
+        let store = Arc::new(MemoryStore::default());
+        let mut config = Config::load_from(store.clone()).unwrap();
+        config.user = Some(user("bit", "first"));
+        config.save().unwrap();
+        assert_eq!(*store.token_writes.lock().unwrap(), 1);
+
+        // Known to be there, both after saving it and after reading it back
+        config.save().unwrap();
+        let mut config = Config::load_from(store.clone()).unwrap();
+        config.save().unwrap();
+        assert_eq!(*store.token_writes.lock().unwrap(), 1);
+
+        config.user.as_mut().unwrap().token = "second".to_string();
+        config.save().unwrap();
+        assert_eq!(*store.token_writes.lock().unwrap(), 2);
+        assert_eq!(store.tokens.lock().unwrap()["bit@example.com"], "second");
+
+        config.delete_keychain_tokens("bit@example.com");
+        assert!(store.tokens.lock().unwrap().is_empty());
+        assert!(config.keychain_tokens().is_empty());
+    }
+
+    fn git_init(dir: &Path) {
+        fs::create_dir_all(dir).unwrap();
+        let status = std::process::Command::new("git")
+            .args(["init", "--quiet"])
+            .current_dir(dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// `path` relative to the current directory, starting with `./`, without changing the
+    /// current directory (which the tests running alongside share)
+    fn relative_to_current_dir(path: &Path) -> String {
+        let current_dir = std::env::current_dir().unwrap().canonicalize().unwrap();
+        let path = path.canonicalize().unwrap();
+        let root = path.ancestors().last().unwrap();
+        let mut relative = PathBuf::from(".");
+        relative.extend(std::iter::repeat_n(
+            "..",
+            current_dir.ancestors().count() - 1,
+        ));
+        relative.push(path.strip_prefix(root).unwrap());
+        relative.display().to_string()
+    }
+
+    #[test]
+    fn the_same_repo_added_differently_is_one_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        git_init(&repo);
+        let relative_repo = relative_to_current_dir(&repo);
+        let absolute_repo = repo.canonicalize().unwrap().display().to_string();
+
+        let mut config = Config::default();
+        for (spelling, is_new) in [
+            (relative_repo.clone(), true),
+            (format!("{}/", relative_repo), false),
+            (absolute_repo.clone(), false),
+            (format!("{}/", absolute_repo), false),
+        ] {
+            let path = utils::NormalisedGitPath::new(spelling.clone()).unwrap();
+            assert_eq!(config.add_repo(&path), is_new, "{}", spelling);
+        }
+        assert_eq!(config.repos, [absolute_repo]);
+
+        // Configs from before add_repo compared paths can hold the same repo several times
+        let missing_repo = dir.path().join("missing").display().to_string();
+        config.repos = vec![
+            relative_repo.clone(),
+            format!("{}/", relative_repo),
+            missing_repo.clone(),
+        ];
+        let (valid_paths, unavailable_repos) = config.get_normalised_paths();
+        assert_eq!(valid_paths.len(), 1);
+        assert_eq!(unavailable_repos, vec![missing_repo.clone()]);
+        assert_eq!(config.prune_repos(), [missing_repo]);
+    }
+
+    #[test]
+    fn a_deleted_repo_can_still_be_found_by_its_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        git_init(&repo);
+        let mut config = Config::default();
+        config.add_repo(&utils::NormalisedGitPath::new(repo.display().to_string()).unwrap());
+        let repo_path = config.repos[0].clone();
+        assert_eq!(
+            config.find_repo(repo.display().to_string()).unwrap(),
+            Some(repo_path.clone())
+        );
+
+        fs::remove_dir_all(&repo).unwrap();
+        for path in [repo_path.clone(), format!("{}/", repo_path)] {
+            assert_eq!(config.find_repo(path).unwrap(), Some(repo_path.clone()));
+        }
+        // Anything else that does not exist is still an error
+        assert!(matches!(
+            config.find_repo(dir.path().join("other").display().to_string()),
+            Err(utils::NormalisedPathError::PathNotExists(_, _))
+        ));
+    }
+
+    #[test]
+    fn a_failed_write_leaves_the_previous_config_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.json");
+        let store = Arc::new(FileStore { path: path.clone() });
+        let mut config = Config::load_from(store.clone()).unwrap();
+        config.feed_window = "3days".to_string();
+        config.save().unwrap();
+
+        // As if the disk filled up halfway through the new config
+        let result = write_atomically_with(&path, |file| {
+            file.write_all(b"{\"feed_window\": \"1")?;
+            Err(std::io::Error::other("No space left on device"))
+        });
+        assert!(result.is_err());
 
-impl From<ConfigError> for Box<dyn error::CustomErrorTrait> {
-    fn from(error: ConfigError) -> Self {
-        Box::new(error)
+        let config = Config::load_from(store).unwrap();
+        assert_eq!(config.feed_window, "3days");
+        // The temporary file was removed too
+        let files: Vec<_> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, ["config.json"]);
     }
 }
@@ -1,8 +1,12 @@
 pub const LOGIN_PATH: &str = "/v1/auth/otp-verification";
 pub const LOGOUT_PATH: &str = "/v1/auth/logout";
+pub const REFRESH_PATH: &str = "/v1/auth/refresh";
 pub const STATUS_PATH: &str = "/v1/pet/status";
 pub const DOES_PET_EXIST_PATH: &str = "/v1/pet/does-pet-exist";
 pub const FEED_PATH: &str = "/v1/pet/feed";
 pub const CHALLENGE_ANS_PATH: &str = "/v1/pet/challenge/answer";
 pub const PLAY_PATH: &str = "/v1/pet/play";
+pub const SLEEP_PATH: &str = "/v1/pet/sleep";
+pub const NEW_PET_PATH: &str = "/v1/pet/new";
+pub const REMOVE_PET_PATH: &str = "/v1/pet/remove";
 pub const UPDATE_CHECK_PATH: &str = "/v1/update/check";
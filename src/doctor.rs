@@ -0,0 +1,206 @@
+use colored::Colorize;
+use serde::Serialize;
+use std::fs;
+use std::process::Command;
+
+use crate::CommandResult;
+use crate::config::Config;
+use crate::git;
+use crate::ui;
+use crate::update;
+use crate::utils;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum CheckStatus {
+    Pass,
+    /// Something worth fixing, that does not stop BitPet from working
+    Warning,
+    Fail,
+}
+
+#[derive(Serialize, Debug)]
+struct Check {
+    name: &'static str,
+    status: CheckStatus,
+    detail: String,
+    /// How to fix it, for checks that did not pass
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, detail: String) -> Check {
+        Check {
+            name,
+            status: CheckStatus::Pass,
+            detail,
+            hint: None,
+        }
+    }
+
+    fn problem(name: &'static str, status: CheckStatus, detail: String, hint: &str) -> Check {
+        Check {
+            name,
+            status,
+            detail,
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+/// Run every check and print a checklist. Fails if any check failed, while warnings are only
+/// printed.
+pub async fn doctor(config: &Config) -> CommandResult {
+    let checks = vec![
+        check_git_installed(),
+        check_git_identity(),
+        check_config_file(config),
+        check_api_reachable(config).await,
+        check_logged_in(config),
+        check_repos(config),
+    ];
+
+    if ui::json_output_enabled() {
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        for check in &checks {
+            let mark = match check.status {
+                CheckStatus::Pass => "✓".green(),
+                CheckStatus::Warning => "!".yellow(),
+                CheckStatus::Fail => "✗".red(),
+            };
+            utils::info!("{} {}: {}", mark, check.name.bold(), check.detail);
+            if let Some(hint) = &check.hint {
+                utils::info!("  {}", hint.dimmed());
+            }
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        return Err(format!("{} of {} checks failed", failed, checks.len()).into());
+    }
+    Ok(())
+}
+
+fn check_git_installed() -> Check {
+    const NAME: &str = "Git";
+    match Command::new("git").arg("--version").output() {
+        Ok(output) if output.status.success() => Check::pass(
+            NAME,
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ),
+        Ok(output) => Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            "Please check your git installation",
+        ),
+        Err(e) => Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!("Could not run git ({})", e),
+            "Please install git (https://git-scm.com/downloads) and make sure it is on your PATH",
+        ),
+    }
+}
+
+fn check_git_identity() -> Check {
+    const NAME: &str = "Git identity";
+    match (git::get_git_username(), git::get_git_email()) {
+        (Ok(username), Ok(email)) => Check::pass(NAME, format!("{} <{}>", username, email)),
+        (Err(e), _) | (_, Err(e)) => Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            e.to_string().trim().to_string(),
+            "Your commits are found by their author. Set it with: git config --global user.name \"Your Name\" && git config --global user.email you@example.com",
+        ),
+    }
+}
+
+/// The file is opened for appending without writing to it, so that it is not changed
+fn check_config_file(config: &Config) -> Check {
+    const NAME: &str = "Config file";
+    let path = config.path();
+    let result = fs::read_to_string(path)
+        .and_then(|_| fs::OpenOptions::new().append(true).open(path).map(|_| ()));
+    match result {
+        Ok(()) => Check::pass(NAME, path.display().to_string()),
+        Err(e) => Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!("{} ({})", path.display(), e),
+            "Please make sure you can read and write this file, or use another one with --config",
+        ),
+    }
+}
+
+async fn check_api_reachable(config: &Config) -> Check {
+    const NAME: &str = "BitPet API";
+    let token = config.user.as_ref().map(|user| user.token.as_str());
+    match update::fetch_update_check(token).await {
+        Ok(_) => Check::pass(NAME, utils::get_api_base_url()),
+        Err(e) => Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!(
+                "{} ({})",
+                utils::get_api_base_url(),
+                utils::redact_secrets(&e.to_string(), token)
+            ),
+            "Please check your internet connection, and the api_base_url setting if you changed it",
+        ),
+    }
+}
+
+fn check_logged_in(config: &Config) -> Check {
+    const NAME: &str = "Login";
+    match &config.user {
+        Some(user) if user.token_expired() && user.refresh_token.is_none() => Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            format!("The session of {} has expired", user.email),
+            "Please login again with: pet login",
+        ),
+        Some(user) => Check::pass(
+            NAME,
+            format!("Logged in as {} ({})", user.username, user.email),
+        ),
+        None => Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            "Not logged in".to_string(),
+            "Please login with: pet login",
+        ),
+    }
+}
+
+fn check_repos(config: &Config) -> Check {
+    const NAME: &str = "Repos";
+    let (paths, unavailable) = config.get_normalised_paths();
+    if paths.is_empty() {
+        return Check::problem(
+            NAME,
+            CheckStatus::Fail,
+            "No git repos added, so your pet cannot be fed".to_string(),
+            "Add the repos you commit to with: pet add-repo <path>",
+        );
+    }
+    if !unavailable.is_empty() {
+        return Check::problem(
+            NAME,
+            CheckStatus::Warning,
+            format!(
+                "{} added, and {} not available anymore",
+                paths.len(),
+                unavailable.len()
+            ),
+            "Remove the unavailable ones with: pet repos prune",
+        );
+    }
+    Check::pass(NAME, format!("{} added", paths.len()))
+}
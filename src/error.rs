@@ -1,21 +1,96 @@
+use serde::Serialize;
+
+/// One entry of an error's backtrace. `line` is 0 and `file` is empty when the location is
+/// not known.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub file: String,
+    pub line: u32,
+    pub function: String,
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.function)?;
+        if !self.file.is_empty() {
+            write!(f, "\n    at {}:{}", self.file, self.line)?;
+        }
+        Ok(())
+    }
+}
+
 pub trait WithBacktrace {
-    fn backtrace(&self) -> &String;
+    fn backtrace(&self) -> &[Frame];
+    fn add_context(&mut self, frame: Frame);
 }
 
-pub trait CustomErrorTrait: std::error::Error + WithBacktrace {}
+/// Process exit codes, one per error category
+pub const EXIT_CODE_GENERIC: i32 = 1;
+pub const EXIT_CODE_AUTH: i32 = 2;
+pub const EXIT_CODE_NETWORK: i32 = 3;
+pub const EXIT_CODE_CONFIG: i32 = 4;
+pub const EXIT_CODE_GIT: i32 = 5;
+
+pub trait CustomErrorTrait: std::error::Error + WithBacktrace {
+    /// The process exit code to use when this error ends the program
+    fn exit_code(&self) -> i32 {
+        EXIT_CODE_GENERIC
+    }
+}
+
+/// Capture the current backtrace as frames. Empty unless backtraces are enabled, e.g. with
+/// RUST_BACKTRACE=1.
+pub fn capture_backtrace() -> Vec<Frame> {
+    let backtrace = std::backtrace::Backtrace::capture();
+    if backtrace.status() != std::backtrace::BacktraceStatus::Captured {
+        return Vec::new();
+    }
+    parse_backtrace(&backtrace.to_string())
+}
+
+/// Parse the std backtrace format, where each frame is a `N: function` line optionally followed
+/// by an `at file:line:column` line
+fn parse_backtrace(backtrace: &str) -> Vec<Frame> {
+    let mut frames: Vec<Frame> = Vec::new();
+    for line in backtrace.lines().map(str::trim) {
+        if let Some(location) = line.strip_prefix("at ") {
+            let Some(frame) = frames.last_mut() else {
+                continue;
+            };
+            // The file can contain ':' itself, so the line and column are split from the end
+            let mut parts = location.rsplitn(3, ':');
+            let (Some(_column), Some(line), Some(file)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            frame.file = file.to_string();
+            frame.line = line.parse().unwrap_or(0);
+        } else if let Some((index, function)) = line.split_once(": ")
+            && index.parse::<usize>().is_ok()
+        {
+            frames.push(Frame {
+                file: String::new(),
+                line: 0,
+                function: function.to_string(),
+            });
+        }
+    }
+    frames
+}
 
 /// Generic wrapper for any error type that adds backtrace support
 #[derive(Debug)]
 pub struct ErrorWithBacktrace<T> {
     error: T,
-    backtrace: String,
+    backtrace: Vec<Frame>,
 }
 
 impl<T> ErrorWithBacktrace<T> {
     pub fn new(error: T) -> Self {
         Self {
             error,
-            backtrace: std::backtrace::Backtrace::capture().to_string(),
+            backtrace: capture_backtrace(),
         }
     }
 }
@@ -33,12 +108,71 @@ impl<T: std::error::Error + 'static> std::error::Error for ErrorWithBacktrace<T>
 }
 
 impl<T> WithBacktrace for ErrorWithBacktrace<T> {
-    fn backtrace(&self) -> &String {
+    fn backtrace(&self) -> &[Frame] {
         &self.backtrace
     }
+
+    fn add_context(&mut self, frame: Frame) {
+        self.backtrace.push(frame);
+    }
 }
 
-impl<T: std::error::Error + 'static> CustomErrorTrait for ErrorWithBacktrace<T> {}
+impl<T: std::error::Error + 'static> CustomErrorTrait for ErrorWithBacktrace<T> {
+    fn exit_code(&self) -> i32 {
+        let error: &dyn std::any::Any = &self.error;
+        if error.is::<reqwest::Error>() || error.is::<reqwest_middleware::Error>() {
+            EXIT_CODE_NETWORK
+        } else if error.is::<std::io::Error>() {
+            EXIT_CODE_CONFIG
+        } else {
+            EXIT_CODE_GENERIC
+        }
+    }
+}
+
+/// Macro to implement `WithBacktrace`, `CustomErrorTrait` and the boxing `From` for an error
+/// enum. An exit code for the error category can optionally be given:
+///
+/// `impl_custom_error!(GitError { GitLogError(_), ... }, exit_code = EXIT_CODE_GIT);`
+///
+/// The backtrace is found by position, not by a `#[backtrace]` attribute (`macro_rules!` cannot
+/// read field attributes): every variant must be a tuple variant whose last field is its
+/// `Vec<Frame>`. Each variant is listed with one pattern per field before the backtrace, so
+/// `GitLogError(String, Vec<Frame>)` is listed as `GitLogError(_)` and
+/// `GitNotInstalled(Vec<Frame>)` as `GitNotInstalled()`. Listing the wrong number of patterns,
+/// or putting the backtrace anywhere else, fails to compile.
+macro_rules! impl_custom_error {
+    ($error_type:ident { $($variant:ident($($field:pat),*)),+ $(,)? } $(, exit_code = $exit_code:expr)? $(,)?) => {
+        impl $crate::error::WithBacktrace for $error_type {
+            fn backtrace(&self) -> &[$crate::error::Frame] {
+                match self {
+                    $($error_type::$variant($($field,)* backtrace) => backtrace,)+
+                }
+            }
+
+            fn add_context(&mut self, frame: $crate::error::Frame) {
+                match self {
+                    $($error_type::$variant($($field,)* backtrace) => backtrace.push(frame),)+
+                }
+            }
+        }
+
+        impl $crate::error::CustomErrorTrait for $error_type {
+            $(
+                fn exit_code(&self) -> i32 {
+                    $exit_code
+                }
+            )?
+        }
+
+        impl From<$error_type> for Box<dyn $crate::error::CustomErrorTrait> {
+            fn from(error: $error_type) -> Self {
+                Box::new(error)
+            }
+        }
+    };
+}
+pub(crate) use impl_custom_error;
 
 /// Macro to generate From implementations for error types
 macro_rules! impl_custom_error_from {
@@ -70,6 +204,39 @@ impl From<String> for Box<dyn CustomErrorTrait> {
     }
 }
 
+impl From<&str> for Box<dyn CustomErrorTrait> {
+    fn from(error: &str) -> Self {
+        error.to_string().into()
+    }
+}
+
+/// Wrapper for anyhow::Error to make it implement Error, with the causes of the anyhow error as
+/// its sources
+#[cfg(feature = "anyhow")]
+#[derive(Debug)]
+pub struct AnyhowError(pub anyhow::Error);
+
+#[cfg(feature = "anyhow")]
+impl std::fmt::Display for AnyhowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl std::error::Error for AnyhowError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+#[cfg(feature = "anyhow")]
+impl From<anyhow::Error> for Box<dyn CustomErrorTrait> {
+    fn from(error: anyhow::Error) -> Self {
+        Box::new(ErrorWithBacktrace::new(AnyhowError(error)))
+    }
+}
+
 // Generate From implementations for error types that already implement Error
 impl_custom_error_from!(reqwest_middleware::Error);
 impl_custom_error_from!(reqwest::Error);
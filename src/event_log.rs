@@ -0,0 +1,61 @@
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::pet::Pet;
+use crate::utils;
+
+/// One line of the event log
+#[derive(Serialize)]
+struct Event<'a, S: Serialize> {
+    /// RFC 3339, in UTC
+    time: String,
+    command: &'a str,
+    /// The status the API answered with, e.g. `FeedSuccess`
+    status: &'a S,
+    /// The pet's stats after the command, when the API sent them
+    pet: Option<&'a Pet>,
+}
+
+static EVENT_LOG_PATH: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// Log events to the file from the BITPET_EVENT_LOG env var, or else the event_log_path config.
+/// Without either, nothing is logged.
+pub fn init_event_log(config_event_log_path: Option<&Path>) {
+    let env_path = std::env::var_os("BITPET_EVENT_LOG")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+    let _ = EVENT_LOG_PATH.set(env_path.or_else(|| config_event_log_path.map(Path::to_path_buf)));
+}
+
+/// Append an event for a command that changed the pet, as one line of JSON. This is best
+/// effort: a failure is only reported with --verbose, and never fails the command.
+pub fn log_event<S: Serialize>(command: &str, status: &S, pet: Option<&Pet>) {
+    let Some(path) = EVENT_LOG_PATH.get().and_then(Option::as_ref) else {
+        return;
+    };
+    let event = Event {
+        time: chrono::Utc::now().to_rfc3339(),
+        command,
+        status,
+        pet,
+    };
+    let result = serde_json::to_string(&event)
+        .map_err(std::io::Error::from)
+        .and_then(|line| {
+            let mut file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            writeln!(file, "{}", line)
+        });
+    if let Err(e) = result {
+        utils::verbose!(
+            "Could not write to the event log {} ({})",
+            path.display(),
+            e
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use crossterm::style::Color;
+use std::io::Write;
+
+use crate::CommandResult;
+use crate::ui::{self, Animation, AnimationWindow};
+
+/// File formats that an animation can be exported to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Plain text, with frames separated by form feeds
+    Txt,
+    /// Text with ANSI colour escapes, with frames separated by form feeds
+    Ansi,
+    /// An animated SVG image
+    Svg,
+}
+
+/// Separates the frames of text exports
+const FORM_FEED: char = '\x0c';
+
+/// Size of a character cell in SVG exports, in pixels
+const SVG_FONT_SIZE: u32 = 16;
+const SVG_CELL_WIDTH: f32 = 9.6;
+const SVG_LINE_HEIGHT: u32 = 20;
+const SVG_MARGIN: u32 = 10;
+const SVG_BACKGROUND: &str = "#1e1e1e";
+const SVG_DEFAULT_FILL: &str = "#d4d4d4";
+
+/// Write every frame of the animation to `out` in the given format. Unlike drawing to the
+/// terminal, no cursor movement or other terminal control codes are written.
+pub fn export_animation<W: Write>(
+    animation: &Animation,
+    format: ExportFormat,
+    out: &mut W,
+) -> CommandResult {
+    if animation.windows.is_empty() {
+        return Err("The animation has no frames to export".into());
+    }
+
+    match format {
+        ExportFormat::Txt => write_text(animation, false, out),
+        ExportFormat::Ansi => write_text(animation, true, out),
+        ExportFormat::Svg => write_svg(animation, out),
+    }
+}
+
+/// A frame positioned on a canvas that fits every frame of the animation, the same way it is
+/// positioned in the box drawn in the terminal
+struct PlacedFrame {
+    lines: Vec<String>,
+    colours: Vec<Vec<String>>,
+    start_x: usize,
+    start_y: usize,
+}
+
+fn place_window(window: &AnimationWindow, canvas_size: (u16, u16)) -> PlacedFrame {
+    let (image, colours, width, height) =
+        ui::pad_image_and_colours(window.image.clone(), window.colours.clone(), None, None);
+    let start_x = (canvas_size.0 / 2)
+        .saturating_sub(width as u16 / 2)
+        .saturating_add_signed(window.delta_x_from_center);
+    let start_y = (canvas_size.1 / 2)
+        .saturating_sub(height as u16 / 2)
+        .saturating_add_signed(window.delta_y_from_center);
+
+    PlacedFrame {
+        lines: image.lines().map(str::to_string).collect(),
+        colours,
+        start_x: start_x as usize,
+        start_y: start_y as usize,
+    }
+}
+
+fn write_text<W: Write>(animation: &Animation, with_colours: bool, out: &mut W) -> CommandResult {
+    let canvas_size = ui::get_animation_content_size(animation);
+
+    for (i, window) in animation.windows.iter().enumerate() {
+        if i > 0 {
+            writeln!(out, "{}", FORM_FEED)?;
+        }
+        let frame = place_window(window, canvas_size);
+        for y in 0..canvas_size.1 as usize {
+            let Some(line) = y
+                .checked_sub(frame.start_y)
+                .and_then(|index| frame.lines.get(index))
+            else {
+                writeln!(out)?;
+                continue;
+            };
+            let line = if with_colours {
+                ui::colourise_line(line, &frame.colours[y - frame.start_y])
+            } else {
+                line.clone()
+            };
+            writeln!(out, "{}{}", " ".repeat(frame.start_x), line)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_svg<W: Write>(animation: &Animation, out: &mut W) -> CommandResult {
+    let canvas_size = ui::get_animation_content_size(animation);
+    let width = (canvas_size.0 as f32 * SVG_CELL_WIDTH).ceil() as u32 + SVG_MARGIN * 2;
+    let height = canvas_size.1 as u32 * SVG_LINE_HEIGHT + SVG_MARGIN * 2;
+
+    let number_of_frames = animation
+        .windows
+        .iter()
+        .map(|window| window.end_frame_inclusive + 1)
+        .max()
+        .unwrap_or(1);
+    let duration_secs = number_of_frames as f64 / animation.fps.max(1) as f64;
+
+    writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    )?;
+    writeln!(
+        out,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        SVG_BACKGROUND
+    )?;
+    writeln!(
+        out,
+        r#"<g font-family="monospace" font-size="{}" fill="{}" xml:space="preserve">"#,
+        SVG_FONT_SIZE, SVG_DEFAULT_FILL
+    )?;
+
+    for window in &animation.windows {
+        let start = window.start_frame_inclusive as f64 / number_of_frames as f64;
+        let end = (window.end_frame_inclusive + 1) as f64 / number_of_frames as f64;
+        // Each frame is only visible from its start to its end time, with the whole animation
+        // looping forever
+        let mut values = Vec::new();
+        let mut key_times = Vec::new();
+        if start > 0.0 {
+            values.push("hidden");
+            key_times.push(0.0);
+        }
+        values.push("visible");
+        key_times.push(start);
+        if end < 1.0 {
+            values.push("hidden");
+            key_times.push(end);
+        }
+
+        if values.len() == 1 {
+            writeln!(out, "<g>")?;
+        } else {
+            writeln!(out, r#"<g visibility="hidden">"#)?;
+            writeln!(
+                out,
+                r#"<animate attributeName="visibility" values="{}" keyTimes="{}" dur="{:.3}s" calcMode="discrete" repeatCount="indefinite"/>"#,
+                values.join(";"),
+                key_times
+                    .iter()
+                    .map(|time| format!("{:.4}", time))
+                    .collect::<Vec<_>>()
+                    .join(";"),
+                duration_secs
+            )?;
+        }
+
+        let frame = place_window(window, canvas_size);
+        for (i, line) in frame.lines.iter().enumerate() {
+            let x = SVG_MARGIN as f32 + frame.start_x as f32 * SVG_CELL_WIDTH;
+            let y = SVG_MARGIN + (frame.start_y + i) as u32 * SVG_LINE_HEIGHT + SVG_FONT_SIZE;
+            writeln!(
+                out,
+                r#"<text x="{:.1}" y="{}">{}</text>"#,
+                x,
+                y,
+                svg_spans(line, &frame.colours[i])
+            )?;
+        }
+        writeln!(out, "</g>")?;
+    }
+
+    writeln!(out, "</g>")?;
+    writeln!(out, "</svg>")?;
+    Ok(())
+}
+
+/// Group the characters of a line into `<tspan>`s of the same colour
+fn svg_spans(line: &str, colours: &[String]) -> String {
+    let mut spans = String::new();
+    let mut current_fill: Option<String> = None;
+    let mut current_text = String::new();
+
+    let mut flush = |fill: &Option<String>, text: &mut String| {
+        if text.is_empty() {
+            return;
+        }
+        match fill {
+            Some(fill) => spans.push_str(&format!(
+                r#"<tspan fill="{}">{}</tspan>"#,
+                fill,
+                escape_xml(text)
+            )),
+            None => spans.push_str(&escape_xml(text)),
+        }
+        text.clear();
+    };
+
+    for (j, ch) in line.chars().enumerate() {
+        let fill = colours
+            .get(j)
+            .and_then(|colour| ui::parse_color(colour))
+            .map(svg_fill);
+        if fill != current_fill {
+            flush(&current_fill, &mut current_text);
+            current_fill = fill;
+        }
+        current_text.push(ch);
+    }
+    flush(&current_fill, &mut current_text);
+
+    spans
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The `#rrggbb` form of a terminal colour, using the usual xterm palette for non RGB colours
+fn svg_fill(colour: Color) -> String {
+    let (r, g, b) = match colour {
+        Color::Rgb { r, g, b } => (r, g, b),
+        Color::AnsiValue(value) => ansi_value_to_rgb(value),
+        Color::Black => ansi_value_to_rgb(0),
+        Color::DarkRed => ansi_value_to_rgb(1),
+        Color::DarkGreen => ansi_value_to_rgb(2),
+        Color::DarkYellow => ansi_value_to_rgb(3),
+        Color::DarkBlue => ansi_value_to_rgb(4),
+        Color::DarkMagenta => ansi_value_to_rgb(5),
+        Color::DarkCyan => ansi_value_to_rgb(6),
+        Color::Grey => ansi_value_to_rgb(7),
+        Color::DarkGrey => ansi_value_to_rgb(8),
+        Color::Red => ansi_value_to_rgb(9),
+        Color::Green => ansi_value_to_rgb(10),
+        Color::Yellow => ansi_value_to_rgb(11),
+        Color::Blue => ansi_value_to_rgb(12),
+        Color::Magenta => ansi_value_to_rgb(13),
+        Color::Cyan => ansi_value_to_rgb(14),
+        Color::White => ansi_value_to_rgb(15),
+        Color::Reset => return SVG_DEFAULT_FILL.to_string(),
+    };
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+fn ansi_value_to_rgb(value: u8) -> (u8, u8, u8) {
+    const BASIC_COLOURS: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (128, 0, 0),
+        (0, 128, 0),
+        (128, 128, 0),
+        (0, 0, 128),
+        (128, 0, 128),
+        (0, 128, 128),
+        (192, 192, 192),
+        (128, 128, 128),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (0, 0, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    match value {
+        0..=15 => BASIC_COLOURS[value as usize],
+        // 6x6x6 colour cube
+        16..=231 => {
+            let index = value - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + 40 * v };
+            (level(index / 36), level((index / 6) % 6), level(index % 6))
+        }
+        // Greyscale ramp
+        232..=255 => {
+            let grey = 8 + 10 * (value - 232);
+            (grey, grey, grey)
+        }
+    }
+}
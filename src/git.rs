@@ -1,32 +1,114 @@
 use crate::utils;
 use chrono::DateTime;
 use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::OnceLock;
 
 use crate::error;
 
+static GIT_AVAILABLE: OnceLock<bool> = OnceLock::new();
+
+/// Check once that git can be run, so that a missing git is reported as such, and not as a
+/// failure of the first git command
+pub fn ensure_git_available() -> Result<(), GitError> {
+    let available = *GIT_AVAILABLE.get_or_init(|| {
+        !matches!(
+            Command::new("git").arg("--version").output(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound
+        )
+    });
+    if available {
+        Ok(())
+    } else {
+        Err(GitError::GitNotInstalled(error::capture_backtrace()))
+    }
+}
+
+/// The error for a git command that could not be run: `GitNotInstalled` if git is missing, or
+/// else `other` with the OS error
+fn command_error(e: std::io::Error, other: fn(String, Vec<error::Frame>) -> GitError) -> GitError {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        GitError::GitNotInstalled(error::capture_backtrace())
+    } else {
+        other(e.to_string(), error::capture_backtrace())
+    }
+}
+
 // NOTE: These are blocking function calls and are being called in an async context. But it is
 // OK cause this is client code anyway.
 
-pub fn is_git(normalised_path: &utils::NormalisedGitPath) -> bool {
-    let mut path = normalised_path.path();
-    if path.join(".git").exists() {
+/// Find the root of the git repo containing `path`, by walking up its ancestors until one
+/// contains a `.git` entry. Stops at the filesystem root.
+pub fn find_git_root(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|dir| is_git(dir))
+        .map(Path::to_path_buf)
+}
+
+/// A file in the root of a repo that stops its commits from being fed, without removing the
+/// repo from BitPet
+pub const IGNORE_FILE: &str = ".bitpetignore";
+
+/// Whether the repo has an `IGNORE_FILE` in its root (and not only in a subdirectory)
+pub fn is_ignored(normalised_path: &utils::NormalisedGitPath) -> bool {
+    normalised_path.path().join(IGNORE_FILE).exists()
+}
+
+/// Whether `dir` has a `.git` directory, or a `.git` file pointing to one, which is what
+/// linked worktrees and submodules have
+pub fn is_git(dir: &Path) -> bool {
+    let git_path = dir.join(".git");
+    if git_path.is_dir() {
         return true;
     }
-    while let Some(parent) = path.parent() {
-        if parent.join(".git").exists() {
-            return true;
+    git_path.is_file()
+        && fs::read_to_string(&git_path)
+            .map(|content| content.trim_start().starts_with("gitdir:"))
+            .unwrap_or(false)
+}
+
+/// How deep `find_git_repos` looks below the directory it was given, so that a mistake like
+/// passing the home directory does not scan the whole disk
+pub const MAX_REPO_SEARCH_DEPTH: usize = 5;
+
+/// Directories that never contain repos worth feeding the pet, but can be huge
+const SKIPPED_DIR_NAMES: &[&str] = &["node_modules", "target", "vendor"];
+
+/// Find the git repos in `dir` and its subdirectories, up to `max_depth` levels down. The
+/// search does not descend into a repo once found, so submodules and nested repos are not
+/// counted twice. Hidden directories, symlinks and `SKIPPED_DIR_NAMES` are skipped.
+pub fn find_git_repos(dir: &Path, max_depth: usize) -> Vec<PathBuf> {
+    let mut repos = Vec::new();
+    let mut dirs_to_search = vec![(dir.to_path_buf(), 0)];
+    while let Some((dir, depth)) = dirs_to_search.pop() {
+        if is_git(&dir) {
+            repos.push(dir);
+            continue;
+        }
+        if depth >= max_depth {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+            if is_dir && !name.starts_with('.') && !SKIPPED_DIR_NAMES.contains(&name.as_str()) {
+                dirs_to_search.push((entry.path(), depth + 1));
+            }
         }
-        path = parent;
     }
-    false
+    repos.sort();
+    repos
 }
 
 // Thread-safe, lazy-initialized static cache for git username
 static CACHED_GIT_USERNAME: OnceLock<String> = OnceLock::new();
 
-fn get_git_username() -> Result<String, GitError> {
+pub fn get_git_username() -> Result<String, GitError> {
     // Try to get from cache first
     if let Some(cached_username) = CACHED_GIT_USERNAME.get() {
         return Ok(cached_username.clone());
@@ -42,7 +124,7 @@ fn get_git_username() -> Result<String, GitError> {
                 if username.is_empty() {
                     Err(GitError::UnableToFetchGitUsername(
                         "Git username not configured in your system.".to_string(),
-                        std::backtrace::Backtrace::capture().to_string(),
+                        error::capture_backtrace(),
                     ))
                 } else {
                     // Cache the username (this can only be set once)
@@ -53,21 +135,18 @@ fn get_git_username() -> Result<String, GitError> {
                 let error_message = String::from_utf8_lossy(&output.stderr);
                 Err(GitError::UnableToFetchGitUsername(
                     error_message.to_string(),
-                    std::backtrace::Backtrace::capture().to_string(),
+                    error::capture_backtrace(),
                 ))
             }
         }
-        Err(e) => Err(GitError::UnableToFetchGitUsername(
-            e.to_string(),
-            std::backtrace::Backtrace::capture().to_string(),
-        )),
+        Err(e) => Err(command_error(e, GitError::UnableToFetchGitUsername)),
     }
 }
 
 // Thread-safe, lazy-initialized static cache for git username
 static CACHED_GIT_EMAIL: OnceLock<String> = OnceLock::new();
 
-fn get_git_email() -> Result<String, GitError> {
+pub fn get_git_email() -> Result<String, GitError> {
     // Try to get from cache first
     if let Some(cached_email) = CACHED_GIT_EMAIL.get() {
         return Ok(cached_email.clone());
@@ -83,7 +162,7 @@ fn get_git_email() -> Result<String, GitError> {
                 if email.is_empty() {
                     Err(GitError::UnableToFetchGitEmail(
                         "Git email not configured in your system.".to_string(),
-                        std::backtrace::Backtrace::capture().to_string(),
+                        error::capture_backtrace(),
                     ))
                 } else {
                     // Cache the email (this can only be set once)
@@ -94,63 +173,124 @@ fn get_git_email() -> Result<String, GitError> {
                 let error_message = String::from_utf8_lossy(&output.stderr);
                 Err(GitError::UnableToFetchGitEmail(
                     error_message.to_string(),
-                    std::backtrace::Backtrace::capture().to_string(),
+                    error::capture_backtrace(),
                 ))
             }
         }
-        Err(e) => Err(GitError::UnableToFetchGitEmail(
-            e.to_string(),
-            std::backtrace::Backtrace::capture().to_string(),
-        )),
+        Err(e) => Err(command_error(e, GitError::UnableToFetchGitEmail)),
     }
 }
 
+/// A name and email that the user commits as. An empty name or email is not matched on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+}
+
+impl Identity {
+    fn matches(&self, author_name: &str, author_email: &str) -> bool {
+        (!self.name.is_empty() && self.name == author_name)
+            || (!self.email.is_empty() && self.email == author_email)
+    }
+}
+
+impl std::fmt::Display for Identity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<{}>", self.email)
+        } else {
+            write!(f, "{} <{}>", self.name, self.email)
+        }
+    }
+}
+
+/// The identities whose commits are the user's: the git-configured name and email, and then
+/// each of `extra_author_emails`
+pub fn user_identities(extra_author_emails: &[String]) -> Result<Vec<Identity>, GitError> {
+    let git_identity = Identity {
+        name: get_git_username()?,
+        email: get_git_email()?,
+    };
+    Ok(with_extra_emails(git_identity, extra_author_emails))
+}
+
+fn with_extra_emails(identity: Identity, extra_author_emails: &[String]) -> Vec<Identity> {
+    let mut identities = vec![identity];
+    identities.extend(extra_author_emails.iter().map(|email| Identity {
+        name: String::new(),
+        email: email.clone(),
+    }));
+    identities
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Commit {
     hash: String,
     time_since_epoch_ms: u64,
+    lines_added: u32,
+    lines_deleted: u32,
+    /// Signature status as reported by git's `%G?` (`G` is a good signature). Only checked when
+    /// signed commits are required, otherwise always `N`.
+    signature_status: char,
+    /// Index of the first of the identities given to `get_commits_for_path_since` that the
+    /// commit matched. Not sent to the API.
+    #[serde(skip)]
+    identity: usize,
+}
+
+impl Commit {
+    pub fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    pub fn identity(&self) -> usize {
+        self.identity
+    }
+
+    pub fn time_since_epoch_ms(&self) -> u64 {
+        self.time_since_epoch_ms
+    }
 }
 
 impl std::fmt::Display for Commit {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Commit: {}\nTime since epoch: {}",
-            self.hash, self.time_since_epoch_ms
+            "Commit: {}\nTime since epoch: {}\nLines: +{} -{}",
+            self.hash, self.time_since_epoch_ms, self.lines_added, self.lines_deleted
         )
     }
 }
 
 #[derive(Debug)]
 pub enum GitError {
-    UnableToFetchGitUsername(String, String),
-    UnableToFetchGitEmail(String, String),
-    PathError(utils::NormalisedPathError, String),
-    GitLogError(String, String),
+    /// The git binary could not be found, as opposed to a git command that failed
+    GitNotInstalled(Vec<error::Frame>),
+    UnableToFetchGitUsername(String, Vec<error::Frame>),
+    UnableToFetchGitEmail(String, Vec<error::Frame>),
+    PathError(utils::NormalisedPathError, Vec<error::Frame>),
+    GitLogError(String, Vec<error::Frame>),
 }
 
-impl error::WithBacktrace for GitError {
-    fn backtrace(&self) -> &String {
-        match self {
-            GitError::UnableToFetchGitUsername(_, s) => s,
-            GitError::UnableToFetchGitEmail(_, s) => s,
-            GitError::PathError(_, s) => s,
-            GitError::GitLogError(_, s) => s,
-        }
-    }
-}
-
-impl error::CustomErrorTrait for GitError {}
-
-impl From<GitError> for Box<dyn error::CustomErrorTrait> {
-    fn from(error: GitError) -> Self {
-        Box::new(error)
-    }
-}
+error::impl_custom_error!(
+    GitError {
+        GitNotInstalled(),
+        UnableToFetchGitUsername(_),
+        UnableToFetchGitEmail(_),
+        PathError(_),
+        GitLogError(_),
+    },
+    exit_code = error::EXIT_CODE_GIT
+);
 
 impl std::fmt::Display for GitError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            GitError::GitNotInstalled(_) => write!(
+                f,
+                "Git is not installed, or not on your PATH. Please install it from https://git-scm.com/downloads"
+            ),
             GitError::UnableToFetchGitUsername(e, _) => write!(f, "{}", e),
             GitError::UnableToFetchGitEmail(e, _) => write!(f, "{}", e),
             GitError::PathError(e, _) => write!(f, "{}", e),
@@ -163,133 +303,519 @@ impl std::error::Error for GitError {}
 
 impl From<utils::NormalisedPathError> for GitError {
     fn from(error: utils::NormalisedPathError) -> Self {
-        GitError::PathError(error, std::backtrace::Backtrace::capture().to_string())
+        GitError::PathError(error, error::capture_backtrace())
     }
 }
 
+/// Separates the fields of a commit in the `git log` output (ASCII unit separator)
+const FIELD_SEPARATOR: char = '\x1f';
+/// Starts each commit in the `git log` output (ASCII record separator). It comes first, since
+/// the `--numstat` lines of a commit are printed after its formatted header.
+const RECORD_SEPARATOR: char = '\x1e';
+
+/// Errors printed by `git log` when HEAD has no commits. Older git versions print the second one.
+const NO_COMMITS_MESSAGES: [&str; 2] = [
+    "does not have any commits yet",
+    "bad default revision 'HEAD'",
+];
+
+/// Get the user's non-merge commits in the repo since the given date. Commits count as the
+/// user's if their author matches the name or email of any of `identities` (see
+/// `user_identities`), and are tagged with the first one they match. If `require_signed` is set,
+/// only commits with a good GPG/SSH signature are returned.
 pub fn get_commits_for_path_since(
     normalised_path: &utils::NormalisedGitPath,
     since: &str,
+    identities: &[Identity],
+    require_signed: bool,
 ) -> Result<Vec<Commit>, GitError> {
-    let username = get_git_username()?;
-    let email = get_git_email()?;
-
+    // Hash, author name, author email and strict ISO-8601 author date, so the output does not
+    // depend on the user's git config or locale. The name and email are resolved through the
+    // repo's .mailmap (if any), so commits made under an old identity still match. Each header
+    // is followed by one `added<TAB>deleted<TAB>path` line per changed file. Verifying
+    // signatures runs gpg/ssh-keygen for every commit, so `%G?` is only asked for when needed.
+    let mut pretty_format = "--pretty=format:%x1e%H%x1f%aN%x1f%aE%x1f%aI".to_string();
+    if require_signed {
+        pretty_format.push_str("%x1f%G?");
+    }
     let git_log_output = Command::new("git")
         .arg("log")
         .arg(format!("--since={}", since))
+        .arg("--no-merges")
+        .arg("--use-mailmap")
+        .arg("--numstat")
+        .arg(pretty_format)
         .current_dir(normalised_path.path())
         .output();
 
     match git_log_output {
         Ok(output) => {
             if output.status.success() {
-                let commits_text = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                if commits_text.is_empty() {
-                    return Ok(Vec::new());
-                }
-
-                let mut commits = Vec::new();
-                let commit_blocks: Vec<&str> = commits_text.split("\ncommit ").collect();
-
-                for (i, block) in commit_blocks.iter().enumerate() {
-                    let block = if i == 0 {
-                        block.strip_prefix("commit ").unwrap_or(block)
-                    } else {
-                        block
-                    };
-
-                    if let Some(commit) = parse_commit_block(block, &username, &email)? {
-                        commits.push(commit);
-                    }
-                }
-
-                Ok(commits)
+                let commits_text = String::from_utf8_lossy(&output.stdout);
+                parse_git_log(&commits_text, identities, require_signed)
             } else {
                 let error_message = String::from_utf8_lossy(&output.stderr);
+                // A freshly initialised repo (or a HEAD that points nowhere) simply has no
+                // commits to feed, which should not stop the other repos from being fed
+                if NO_COMMITS_MESSAGES
+                    .iter()
+                    .any(|message| error_message.contains(message))
+                {
+                    return Ok(Vec::new());
+                }
                 Err(GitError::GitLogError(
                     error_message.to_string(),
-                    std::backtrace::Backtrace::capture().to_string(),
+                    error::capture_backtrace(),
                 ))
             }
         }
-        Err(e) => Err(GitError::GitLogError(
-            e.to_string(),
-            std::backtrace::Backtrace::capture().to_string(),
-        )),
+        Err(e) => Err(command_error(e, GitError::GitLogError)),
     }
 }
 
-fn parse_commit_block(
-    block: &str,
-    expected_username: &str,
-    expected_email: &str,
-) -> Result<Option<Commit>, GitError> {
-    let lines: Vec<&str> = block.lines().collect();
-    if lines.len() < 3 {
-        return Ok(None);
+/// Parse the whole `git log` output into the commits by one of `identities`, keeping only those
+/// with a good signature if `require_signed`
+fn parse_git_log(
+    commits_text: &str,
+    identities: &[Identity],
+    require_signed: bool,
+) -> Result<Vec<Commit>, GitError> {
+    let mut commits = Vec::new();
+    for block in commits_text.split(RECORD_SEPARATOR) {
+        let block = block.trim();
+        if block.is_empty() {
+            continue;
+        }
+        if let Some(commit) = parse_commit_block(block, identities)?
+            && (!require_signed || commit.signature_status == 'G')
+        {
+            commits.push(commit);
+        }
     }
+    Ok(commits)
+}
 
-    // Parse commit hash (first line)
-    let hash = lines[0].trim().to_string();
-    if hash.is_empty() {
-        return Ok(None);
-    }
+/// Parse one record of the `git log` output (a `hash<US>name<US>email<US>date[<US>signature]`
+/// line followed by numstat lines), returning `None` if the commit was authored by someone else
+fn parse_commit_block(block: &str, identities: &[Identity]) -> Result<Option<Commit>, GitError> {
+    let mut lines = block.lines();
+    let header = lines.next().unwrap_or_default();
+    let fields: Vec<&str> = header.split(FIELD_SEPARATOR).collect();
+    let (hash, author_name, author_email, date_str, signature_status) = match fields[..] {
+        [hash, name, email, date] => (hash, name, email, date, 'N'),
+        [hash, name, email, date, signature] if signature.chars().count() == 1 => (
+            hash,
+            name,
+            email,
+            date,
+            signature.chars().next().unwrap_or('N'),
+        ),
+        _ => {
+            return Err(GitError::GitLogError(
+                format!("Unexpected git log output: '{}'", block),
+                error::capture_backtrace(),
+            ));
+        }
+    };
 
-    // Parse author line (second line should be "Author: name <email>")
-    let author_line = lines[1];
-    if !author_line.starts_with("Author: ") {
+    let Some(identity) = identities
+        .iter()
+        .position(|identity| identity.matches(author_name, author_email))
+    else {
         return Ok(None);
-    }
-
-    let author_info = &author_line[8..]; // Skip "Author: "
-
-    // Extract author name and email from "name <email>" format
-    let (author_name, author_email) = if let Some(email_start) = author_info.find(" <") {
-        let name = author_info[..email_start].trim();
-        let email_part = &author_info[email_start + 2..]; // Skip " <"
-        let email = if let Some(email_end) = email_part.find('>') {
-            email_part[..email_end].trim()
-        } else {
-            email_part.trim()
-        };
-        (name, email)
-    } else {
-        (author_info.trim(), "")
     };
 
-    // Check if this commit is from the expected username OR email
-    if author_name != expected_username && author_email != expected_email {
-        return Ok(None);
-    }
+    let timestamp_ms = parse_git_date(date_str)?;
 
-    // Parse date line (third line should be "Date:   ...")
-    let date_line = lines[2];
-    if !date_line.starts_with("Date:") {
-        return Ok(None);
+    let mut lines_added: u32 = 0;
+    let mut lines_deleted: u32 = 0;
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        let mut counts = line.split('\t');
+        let (Some(added), Some(deleted)) = (counts.next(), counts.next()) else {
+            return Err(GitError::GitLogError(
+                format!("Unexpected git numstat line: '{}'", line),
+                error::capture_backtrace(),
+            ));
+        };
+        // Binary files are reported as "-" and count as zero lines
+        lines_added = lines_added.saturating_add(added.parse().unwrap_or(0));
+        lines_deleted = lines_deleted.saturating_add(deleted.parse().unwrap_or(0));
     }
 
-    let date_str = date_line[5..].trim(); // Skip "Date:"
-
-    // Parse the date string to get timestamp
-    // Format: "Fri Sep 19 20:12:42 2025 +0530"
-    let timestamp_ms = parse_git_date(date_str)?;
-
     Ok(Some(Commit {
-        hash,
+        hash: hash.to_string(),
         time_since_epoch_ms: timestamp_ms,
+        lines_added,
+        lines_deleted,
+        signature_status,
+        identity,
     }))
 }
 
 fn parse_git_date(date_str: &str) -> Result<u64, GitError> {
-    // Git date format is: "Day Mon DD HH:MM:SS YYYY +ZZZZ"
-    let parsed_date =
-        DateTime::parse_from_str(date_str, "%a %b %d %H:%M:%S %Y %z").map_err(|e| {
+    let date_str = date_str.trim();
+    // Git's strict ISO-8601 format (%aI), e.g. "2025-09-19T20:12:42+05:30", is what `git log` is
+    // asked for. The others are what the date looks like with other --date formats, in case a
+    // git config or wrapper overrides it.
+    let parsed_date = DateTime::parse_from_rfc3339(date_str)
+        .or_else(|_| DateTime::parse_from_rfc2822(date_str))
+        // --date=iso, e.g. "2025-09-19 20:12:42 +0530"
+        .or_else(|_| DateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S %z"))
+        // --date=default, e.g. "Fri Sep 19 20:12:42 2025 +0530"
+        .or_else(|_| DateTime::parse_from_str(date_str, "%a %b %e %H:%M:%S %Y %z"))
+        .map_err(|e| {
             GitError::GitLogError(
                 format!("Failed to parse git date '{}': {}", date_str, e),
-                std::backtrace::Backtrace::capture().to_string(),
+                error::capture_backtrace(),
             )
         })?;
 
     // Convert to milliseconds since Unix epoch
     Ok(parsed_date.timestamp_millis() as u64)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn me() -> Vec<Identity> {
+        vec![Identity {
+            name: "Me".to_string(),
+            email: "me@example.com".to_string(),
+        }]
+    }
+
+    /// A `git log` record as asked for by `get_commits_for_path_since`, with the signature
+    /// column if one is given
+    fn record(hash: &str, author: &str, signature: Option<&str>) -> String {
+        record_by(hash, author, &format!("{author}@example.com"), signature)
+    }
+
+    fn record_by(hash: &str, name: &str, email: &str, signature: Option<&str>) -> String {
+        let mut header = format!("\x1e{hash}\x1f{name}\x1f{email}\x1f2025-09-19T20:12:42+05:30");
+        if let Some(signature) = signature {
+            header.push_str(&format!("\x1f{signature}"));
+        }
+        format!("{header}\n\n3\t1\tsrc/main.rs\n-\t-\tlogo.png\n")
+    }
+
+    fn hashes(commits: &[Commit]) -> Vec<&str> {
+        commits.iter().map(Commit::hash).collect()
+    }
+
+    #[test]
+    fn only_good_signatures_are_kept_when_required() {
+        let output = [
+            record("good", "Me", Some("G")),
+            record("unsigned", "Me", Some("N")),
+            record("bad", "Me", Some("B")),
+            record("theirs", "Someone", Some("G")),
+        ]
+        .concat();
+
+        let commits = parse_git_log(&output, &me(), true).unwrap();
+        assert_eq!(hashes(&commits), ["good"]);
+        assert_eq!(commits[0].lines_added, 3);
+        assert_eq!(commits[0].lines_deleted, 1);
+        assert_eq!(commits[0].signature_status, 'G');
+    }
+
+    #[test]
+    fn a_missing_signature_column_counts_as_unsigned() {
+        let output = [record("first", "Me", None), record("second", "Me", None)].concat();
+
+        let commits = parse_git_log(&output, &me(), false).unwrap();
+        assert_eq!(hashes(&commits), ["first", "second"]);
+        assert!(commits.iter().all(|commit| commit.signature_status == 'N'));
+        assert!(parse_git_log(&output, &me(), true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_malformed_record_is_an_error() {
+        for output in ["\x1eabc\x1fMe", &record("long", "Me", Some("GG"))] {
+            let message = parse_git_log(output, &me(), false).unwrap_err().to_string();
+            assert!(message.contains("Unexpected git log output"), "{}", message);
+        }
+        assert!(parse_git_log("", &me(), true).unwrap().is_empty());
+    }
+
+    /// A --since before any test commit. Git only reads `@<number>` as a Unix timestamp when the
+    /// number is large, and takes `@0` to mean now.
+    const SINCE_2001: &str = "@1000000000";
+
+    /// Run git in `dir`, committing as `Me` unless the arguments say otherwise
+    fn git(dir: &Path, args: &[&str]) {
+        let output = Command::new("git")
+            .args(["-c", "user.name=Me", "-c", "user.email=me@example.com"])
+            .args([
+                "-c",
+                "init.defaultBranch=main",
+                "-c",
+                "commit.gpgSign=false",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?}: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    fn git_init(dir: &Path) {
+        git(dir, &["init", "--quiet"]);
+    }
+
+    fn commit_file(dir: &Path, file: &str, extra_args: &[&str]) {
+        fs::write(dir.join(file), file).unwrap();
+        git(dir, &["add", file]);
+        git(
+            dir,
+            &[&["commit", "--quiet", "-m", file], extra_args].concat(),
+        );
+    }
+
+    #[test]
+    fn numstat_lines_are_summed_per_commit() {
+        let header = |hash: &str| {
+            format!("\x1e{hash}\x1fMe\x1fme@example.com\x1f2025-09-19T20:12:42+05:30\n\n")
+        };
+        let output = [
+            header("code"),
+            "10\t2\tsrc/main.rs\n5\t0\tsrc/git.rs\n-\t-\tlogo.png\n".to_string(),
+            header("binary-only"),
+            "-\t-\tlogo.png\n".to_string(),
+            // e.g. a commit that only changed a file's mode
+            header("nothing"),
+        ]
+        .concat();
+
+        let commits = parse_git_log(&output, &me(), false).unwrap();
+        let lines: Vec<(&str, u32, u32)> = commits
+            .iter()
+            .map(|commit| (commit.hash(), commit.lines_added, commit.lines_deleted))
+            .collect();
+        assert_eq!(
+            lines,
+            [("code", 15, 2), ("binary-only", 0, 0), ("nothing", 0, 0)]
+        );
+
+        let malformed = format!("{}12\n", header("malformed"));
+        let message = parse_git_log(&malformed, &me(), false)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            message.contains("Unexpected git numstat line"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn a_commit_by_an_extra_email_is_counted() {
+        let git_identity = Identity {
+            name: "Me".to_string(),
+            email: "me@example.com".to_string(),
+        };
+        let identities = with_extra_emails(git_identity, &["me@work.example".to_string()]);
+        let output = [
+            record_by("home", "Me", "me@example.com", None),
+            // Another name on the work machine, so only the email matches
+            record_by("work", "Me At Work", "me@work.example", None),
+            record_by("theirs", "Someone", "someone@work.example", None),
+        ]
+        .concat();
+
+        let commits = parse_git_log(&output, &identities, false).unwrap();
+        assert_eq!(hashes(&commits), ["home", "work"]);
+        assert_eq!(commits[1].identity(), 1);
+    }
+
+    #[test]
+    fn commits_are_kept_and_tagged_by_the_first_identity_they_match() {
+        let identities = [
+            // A bot that commits under several emails, matched by its name
+            Identity {
+                name: "Release Bot".to_string(),
+                email: String::new(),
+            },
+            // Matched by email, whatever name the commit was made under
+            Identity {
+                name: String::new(),
+                email: "me@example.com".to_string(),
+            },
+        ];
+        let cases = [
+            ("bot", "Release Bot", "bot@ci.example", Some(0)),
+            ("bot-as-me", "Release Bot", "me@example.com", Some(0)),
+            ("me", "Me", "me@example.com", Some(1)),
+            ("empty-name", "", "someone@example.com", None),
+            ("empty-email", "Someone", "", None),
+            ("theirs", "Someone", "someone@example.com", None),
+        ];
+        let output: String = cases
+            .iter()
+            .map(|(hash, name, email, _)| record_by(hash, name, email, None))
+            .collect();
+
+        let commits = parse_git_log(&output, &identities, false).unwrap();
+        let kept: Vec<(&str, usize)> = commits
+            .iter()
+            .map(|commit| (commit.hash(), commit.identity()))
+            .collect();
+        let expected: Vec<(&str, usize)> = cases
+            .iter()
+            .filter_map(|(hash, _, _, identity)| identity.map(|identity| (*hash, identity)))
+            .collect();
+        assert_eq!(kept, expected);
+    }
+
+    #[test]
+    fn merge_commits_are_not_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        git_init(dir.path());
+        commit_file(dir.path(), "first", &[]);
+        git(dir.path(), &["checkout", "--quiet", "-b", "feature"]);
+        commit_file(dir.path(), "feature", &[]);
+        git(dir.path(), &["checkout", "--quiet", "main"]);
+        commit_file(dir.path(), "second", &[]);
+        git(
+            dir.path(),
+            &["merge", "--quiet", "--no-ff", "-m", "merge", "feature"],
+        );
+        let path = utils::NormalisedGitPath::new(dir.path().display().to_string()).unwrap();
+
+        let commits = get_commits_for_path_since(&path, SINCE_2001, &me(), false).unwrap();
+        assert_eq!(commits.len(), 3);
+        // Each commit added its one file, which a merge commit does not
+        assert!(commits.iter().all(|commit| commit.lines_added == 1));
+    }
+
+    #[test]
+    fn a_repo_without_commits_has_nothing_to_feed() {
+        let dir = tempfile::tempdir().unwrap();
+        git_init(dir.path());
+        let path = utils::NormalisedGitPath::new(dir.path().display().to_string()).unwrap();
+
+        for require_signed in [false, true] {
+            let commits =
+                get_commits_for_path_since(&path, SINCE_2001, &me(), require_signed).unwrap();
+            assert!(commits.is_empty());
+        }
+    }
+
+    #[test]
+    fn linked_worktrees_and_submodules_are_repos() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main");
+        let library = dir.path().join("library");
+        for repo in [&main, &library] {
+            fs::create_dir_all(repo).unwrap();
+            git_init(repo);
+            commit_file(repo, "README", &[]);
+        }
+        let worktree = dir.path().join("worktree");
+        git(
+            &main,
+            &[
+                "worktree",
+                "add",
+                "--quiet",
+                "-b",
+                "worktree",
+                &worktree.display().to_string(),
+            ],
+        );
+        let submodule = main.join("library");
+        git(
+            &main,
+            &[
+                "-c",
+                "protocol.file.allow=always",
+                "submodule",
+                "add",
+                "--quiet",
+                &library.display().to_string(),
+                "library",
+            ],
+        );
+
+        // Both have a .git file instead of a directory
+        for repo in [&worktree, &submodule] {
+            assert!(repo.join(".git").is_file());
+            assert!(is_git(repo));
+            let nested = repo.join("src").join("nested");
+            fs::create_dir_all(&nested).unwrap();
+            assert!(!is_git(&nested));
+            assert_eq!(find_git_root(&nested).as_ref(), Some(repo));
+        }
+        assert_eq!(find_git_root(&main.join("README")), Some(main));
+    }
+
+    #[test]
+    fn a_path_outside_any_repo_has_no_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        // A .git file without a gitdir line is not a repo
+        fs::write(dir.path().join("a").join(".git"), "something else\n").unwrap();
+
+        assert!(!is_git(&dir.path().join("a")));
+        // The search goes up to the filesystem root and stops there
+        assert_eq!(find_git_root(&nested), None);
+        let root = nested.ancestors().last().unwrap();
+        assert_eq!(find_git_root(root), None);
+    }
+
+    #[test]
+    fn a_git_binary_that_cannot_be_found_is_reported_as_not_installed() {
+        let not_found = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(matches!(
+            command_error(not_found, GitError::GitLogError),
+            GitError::GitNotInstalled(_)
+        ));
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(matches!(
+            command_error(denied, GitError::GitLogError),
+            GitError::GitLogError(_, _)
+        ));
+    }
+
+    #[test]
+    fn parses_the_date_formats_git_log_can_print() {
+        let expected = Utc
+            .with_ymd_and_hms(2025, 9, 19, 14, 42, 42)
+            .unwrap()
+            .timestamp_millis() as u64;
+        let cases = [
+            // --date=iso-strict, what `git log` is asked for
+            "2025-09-19T20:12:42+05:30",
+            // --date=rfc
+            "Fri, 19 Sep 2025 20:12:42 +0530",
+            // --date=iso
+            "2025-09-19 20:12:42 +0530",
+            // --date=default
+            "Fri Sep 19 20:12:42 2025 +0530",
+            "  2025-09-19T20:12:42+05:30\n",
+        ];
+        for date in cases {
+            assert_eq!(parse_git_date(date).ok(), Some(expected), "{:?}", date);
+        }
+    }
+
+    #[test]
+    fn rejects_a_malformed_date_and_names_it() {
+        for date in ["", "yesterday", "2 hours ago", "2025-13-40T99:00:00+00:00"] {
+            let message = parse_git_date(date).unwrap_err().to_string();
+            assert!(
+                message.contains(&format!("Failed to parse git date '{}'", date)),
+                "{}",
+                message
+            );
+        }
+    }
+}
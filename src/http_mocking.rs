@@ -1,21 +1,24 @@
 use crate::constants::{
-    CHALLENGE_ANS_PATH, DOES_PET_EXIST_PATH, FEED_PATH, LOGIN_PATH, LOGOUT_PATH, PLAY_PATH,
-    STATUS_PATH, UPDATE_CHECK_PATH,
+    CHALLENGE_ANS_PATH, DOES_PET_EXIST_PATH, FEED_PATH, LOGIN_PATH, LOGOUT_PATH, NEW_PET_PATH,
+    PLAY_PATH, REFRESH_PATH, REMOVE_PET_PATH, SLEEP_PATH, STATUS_PATH, UPDATE_CHECK_PATH,
 };
 use crate::pet::StatusAPIResult;
 use crate::pet::{
     Challenge, ChallengeAnswerAPIResult, ChallengeAnswerStatus, ChallengeAnswerType, FeedAPIResult,
-    FeedStatus, Pet, PlayAPIResult, PlayStatus,
+    FeedStatus, NewPetAPIResult, Pet, PlayAPIResult, PlayStatus, SleepAPIResult, SleepStatus,
 };
 use http::Extensions;
 use reqwest::{Body, Request, Response};
 use reqwest_middleware::{Middleware, Next, Result};
 use serde::Deserialize;
 use serde_json::json;
+use sha2::{Digest, Sha256};
 use std::sync::LazyLock;
 
-use crate::UpdateCheckAPIResult;
+use crate::theme;
 use crate::ui::{Animation, AnimationWindow};
+use crate::update::UpdateCheckAPIResult;
+use crate::utils;
 pub struct MockingMiddleware;
 
 #[derive(Deserialize, Debug)]
@@ -23,10 +26,24 @@ struct LoginRequest {
     otp: String,
 }
 
+#[derive(Deserialize, Debug)]
+struct RefreshRequest {
+    refresh_token: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct NewPetRequest {
+    name: String,
+}
+
 const MOCK_TOKEN: &str = "mock-token";
+const MOCK_REFRESH_TOKEN: &str = "mock-refresh-token";
 const MOCK_EMAIL: &str = "mock@bitpet.dev";
 const MOCK_USERNAME: &str = "mock-username";
 const MOCK_OTP: &str = "-9999";
+const MOCK_VERSION: &str = "99.0.0";
+const MOCK_RELEASE_PATH: &str = "/mock/release/pet";
+const MOCK_RELEASE_BINARY: &str = "#!/bin/sh\necho \"BitPet 99.0.0 (mock)\"\n";
 
 pub static PET: LazyLock<Pet> = LazyLock::new(|| Pet {
     user_id: "mock-user-id".to_string(),
@@ -37,8 +54,52 @@ pub static PET: LazyLock<Pet> = LazyLock::new(|| Pet {
     happiness: 40.0,
     created_at: 0,
     streak: 0,
+    energy: 60.0,
 });
 
+/// A response from the fixtures file, which is a JSON object keyed by method and path, e.g.
+/// `{"POST /v1/pet/feed": {"status": 200, "body": {...}}}`. A string body is sent as it is, and
+/// any other JSON value is sent serialised. Headers can be added too, e.g. a rate limit is
+/// `{"status": 429, "headers": {"Retry-After": "30"}}`.
+#[derive(Deserialize, Debug)]
+struct Fixture {
+    #[serde(default = "default_fixture_status")]
+    status: u16,
+    #[serde(default)]
+    headers: std::collections::HashMap<String, String>,
+    #[serde(default)]
+    body: serde_json::Value,
+}
+
+fn default_fixture_status() -> u16 {
+    200
+}
+
+/// Find the response for the request in the file at BITPET_MOCK_FIXTURES, if it is set. The file
+/// is read for every request, so it can be edited between commands.
+fn find_fixture(req: &Request) -> Result<Option<Fixture>> {
+    let Some(fixtures_path) = std::env::var_os("BITPET_MOCK_FIXTURES") else {
+        return Ok(None);
+    };
+    let load_error = |e: &dyn std::fmt::Display| {
+        reqwest_middleware::Error::middleware(std::io::Error::other(format!(
+            "Could not load mock fixtures from {}: {}",
+            std::path::Path::new(&fixtures_path).display(),
+            e
+        )))
+    };
+    let content = std::fs::read_to_string(&fixtures_path).map_err(|e| load_error(&e))?;
+    let mut fixtures: std::collections::HashMap<String, Fixture> =
+        serde_json::from_str(&content).map_err(|e| load_error(&e))?;
+
+    let key = format!("{} {}", req.method(), req.url().path());
+    let key = fixtures
+        .keys()
+        .find(|fixture_key| fixture_key.trim().eq_ignore_ascii_case(&key))
+        .cloned();
+    Ok(key.and_then(|key| fixtures.remove(&key)))
+}
+
 #[async_trait::async_trait]
 impl Middleware for MockingMiddleware {
     async fn handle(
@@ -47,6 +108,22 @@ impl Middleware for MockingMiddleware {
         extensions: &mut Extensions,
         next: Next<'_>,
     ) -> Result<Response> {
+        if let Some(fixture) = find_fixture(&req)? {
+            let body = match fixture.body {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(body) => body,
+                body => body.to_string(),
+            };
+            let mut response = http::Response::builder().status(fixture.status);
+            for (name, value) in &fixture.headers {
+                response = response.header(name, value);
+            }
+            return Ok(response
+                .body(Body::from(body))
+                .map_err(reqwest_middleware::Error::middleware)?
+                .into());
+        }
+
         let path = req.url().path();
         if path == LOGIN_PATH {
             let body = req.body().unwrap().as_bytes().unwrap();
@@ -58,141 +135,203 @@ impl Middleware for MockingMiddleware {
                         serde_json::to_string(&json!({
                             "username": MOCK_USERNAME,
                             "email": MOCK_EMAIL,
-                            "token": MOCK_TOKEN
+                            "token": MOCK_TOKEN,
+                            "refresh_token": MOCK_REFRESH_TOKEN
                         }))
                         .unwrap(),
                     ))
                     .unwrap()
                     .into());
             }
+        } else if path == REFRESH_PATH {
+            let body = req.body().unwrap().as_bytes().unwrap();
+            let refresh_request: RefreshRequest = serde_json::from_slice(body).unwrap();
+            if refresh_request.refresh_token == MOCK_REFRESH_TOKEN {
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body(Body::from(
+                        serde_json::to_string(&json!({ "token": MOCK_TOKEN })).unwrap(),
+                    ))
+                    .unwrap()
+                    .into());
+            }
         } else if path == LOGOUT_PATH {
-            let token = req.headers().get("Authorization");
-            if !token.is_none() {
-                let token = token.unwrap().to_str();
-                if token.is_ok() && token.unwrap() == "Bearer ".to_owned() + MOCK_TOKEN {
-                    return Ok(http::Response::builder()
-                        .status(200)
-                        .body(Body::from("Logged out successfully!"))
-                        .unwrap()
-                        .into());
-                }
+            if has_mock_token(&req) {
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body(Body::from("Logged out successfully!"))
+                    .unwrap()
+                    .into());
             }
         } else if path == DOES_PET_EXIST_PATH {
-            let token = req.headers().get("Authorization");
-            if !token.is_none() {
-                let token = token.unwrap().to_str();
-                if token.is_ok() && token.unwrap() == "Bearer ".to_owned() + MOCK_TOKEN {
-                    return Ok(http::Response::builder()
-                        .status(200)
-                        .body("")
-                        .unwrap()
-                        .into());
-                }
+            if has_mock_token(&req) {
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body("")
+                    .unwrap()
+                    .into());
             }
         } else if path == STATUS_PATH {
-            let token = req.headers().get("Authorization");
-            if !token.is_none() {
-                let token = token.unwrap().to_str();
-                if token.is_ok() && token.unwrap() == "Bearer ".to_owned() + MOCK_TOKEN {
-                    let result = StatusAPIResult {
-                        animation: generate_pet_status_animation(),
-                        pet: PET.clone(),
-                    };
-                    return Ok(http::Response::builder()
-                        .status(200)
-                        .body(Body::from(serde_json::to_string(&result).unwrap()))
-                        .unwrap()
-                        .into());
-                }
+            if has_mock_token(&req) {
+                let result = StatusAPIResult {
+                    animation: generate_pet_status_animation(),
+                    pet: PET.clone(),
+                };
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body(Body::from(serde_json::to_string(&result).unwrap()))
+                    .unwrap()
+                    .into());
             }
         } else if path == FEED_PATH {
-            let token = req.headers().get("Authorization");
-            if !token.is_none() {
-                let token = token.unwrap().to_str();
-                if token.is_ok() && token.unwrap() == "Bearer ".to_owned() + MOCK_TOKEN {
-                    return Ok(http::Response::builder()
-                        .status(200)
-                        .body(Body::from(
-                            serde_json::to_string(&FeedAPIResult {
-                                animation: None,
-                                text_before_animation: None,
-                                status: FeedStatus::AskForChallenge,
-                                challenge: Some(Challenge {
-                                    id: "mock-challenge-id".to_string(),
-                                    description: "You are given an array in which you need to sort all the numbers.\nImplement this!".to_string(),
-                                    answer_type: ChallengeAnswerType::Text,
-                                }),
-                                pet: None,
-                            })
-                            .unwrap(),
-                        ))
-                        .unwrap()
-                        .into());
-                }
+            if has_mock_token(&req) {
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body(Body::from(
+                        serde_json::to_string(&FeedAPIResult {
+                            animation: None,
+                            text_before_animation: None,
+                            status: FeedStatus::AskForChallenge,
+                            challenge: Some(Challenge {
+                                id: "mock-challenge-id".to_string(),
+                                description: "You are given an array in which you need to sort all the numbers.\nImplement this!".to_string(),
+                                answer_type: ChallengeAnswerType::Text,
+                            }),
+                            pet: None,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap()
+                    .into());
             }
         } else if path == CHALLENGE_ANS_PATH {
-            let token = req.headers().get("Authorization");
-            if !token.is_none() {
-                let token = token.unwrap().to_str();
-                if token.is_ok() && token.unwrap() == "Bearer ".to_owned() + MOCK_TOKEN {
-                    return Ok(http::Response::builder()
-                        .status(200)
-                        .body(Body::from(
-                            serde_json::to_string(&ChallengeAnswerAPIResult {
-                                feed_result: None,
-                                status: ChallengeAnswerStatus::Incorrect,
-                            })
-                            .unwrap(),
-                        ))
-                        .unwrap()
-                        .into());
-                }
+            if has_mock_token(&req) {
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body(Body::from(
+                        serde_json::to_string(&ChallengeAnswerAPIResult {
+                            feed_result: None,
+                            status: ChallengeAnswerStatus::Incorrect,
+                            feedback: Some(
+                                "The numbers are not sorted in ascending order.".to_string(),
+                            ),
+                            attempts_remaining: Some(2),
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap()
+                    .into());
             }
         } else if path == PLAY_PATH {
-            let token = req.headers().get("Authorization");
-            if !token.is_none() {
-                let token = token.unwrap().to_str();
-                if token.is_ok() && token.unwrap() == "Bearer ".to_owned() + MOCK_TOKEN {
-                    return Ok(http::Response::builder()
-                        .status(200)
-                        .body(Body::from(
-                            serde_json::to_string(&PlayAPIResult {
-                                animation: None,
-                                text_before_animation: Some(format!(
-                                    "{} is in no mood to play right now! Please try later, or consider feeding them instead or just taking a break!", 
-                                    PET.name
-                                )),
-                                status: PlayStatus::TooMuchPlay,
-                                pet: Some(PET.clone()),
-                            })
-                            .unwrap(),
-                        ))
-                        .unwrap()
-                        .into());
-                }
+            if has_mock_token(&req) {
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body(Body::from(
+                        serde_json::to_string(&PlayAPIResult {
+                            animation: None,
+                            text_before_animation: Some(format!(
+                                "{} is in no mood to play right now! Please try later, or consider feeding them instead or just taking a break!", 
+                                PET.name
+                            )),
+                            status: PlayStatus::TooMuchPlay,
+                            pet: Some(PET.clone()),
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap()
+                    .into());
             }
-        } else if path == UPDATE_CHECK_PATH {
-            let token = req.headers().get("Authorization");
-            if !token.is_none() {
-                let token = token.unwrap().to_str();
-                if token.is_ok() && token.unwrap() == "Bearer ".to_owned() + MOCK_TOKEN {
-                    return Ok(http::Response::builder()
-                        .status(200)
-                        .body(Body::from(
-                            serde_json::to_string(&UpdateCheckAPIResult {
-                                update_available: true,
-                            })
-                            .unwrap(),
-                        ))
-                        .unwrap()
-                        .into());
-                }
+        } else if path == SLEEP_PATH {
+            if has_mock_token(&req) {
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body(Body::from(
+                        serde_json::to_string(&SleepAPIResult {
+                            animation: None,
+                            text_before_animation: Some(format!(
+                                "{} curls up and takes a nap. Zzz...",
+                                PET.name
+                            )),
+                            status: SleepStatus::SleepSuccess,
+                            pet: Some(Pet {
+                                energy: 100.0,
+                                ..PET.clone()
+                            }),
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap()
+                    .into());
+            }
+        } else if path == NEW_PET_PATH {
+            if has_mock_token(&req) {
+                let body = req.body().unwrap().as_bytes().unwrap();
+                let new_pet_request: NewPetRequest = serde_json::from_slice(body).unwrap();
+                let pet = Pet {
+                    name: new_pet_request.name,
+                    ..PET.clone()
+                };
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body(Body::from(
+                        serde_json::to_string(&NewPetAPIResult {
+                            animation: Some(generate_pet_status_animation()),
+                            text_before_animation: Some(format!(
+                                "Say hello to {}! Feed them by writing code.",
+                                pet.name
+                            )),
+                            pet,
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap()
+                    .into());
             }
+        } else if path == REMOVE_PET_PATH {
+            if has_mock_token(&req) {
+                return Ok(http::Response::builder()
+                    .status(200)
+                    .body("")
+                    .unwrap()
+                    .into());
+            }
+        } else if path == UPDATE_CHECK_PATH && has_mock_token(&req) {
+            return Ok(http::Response::builder()
+                .status(200)
+                .body(Body::from(
+                    serde_json::to_string(&UpdateCheckAPIResult {
+                        update_available: true,
+                        latest_version: Some(MOCK_VERSION.to_string()),
+                        download_url: Some(utils::get_api_base_url() + MOCK_RELEASE_PATH),
+                        sha256: Some(
+                            Sha256::digest(MOCK_RELEASE_BINARY)
+                                .iter()
+                                .map(|byte| format!("{:02x}", byte))
+                                .collect(),
+                        ),
+                    })
+                    .unwrap(),
+                ))
+                .unwrap()
+                .into());
+        } else if path == MOCK_RELEASE_PATH {
+            return Ok(http::Response::builder()
+                .status(200)
+                .body(Body::from(MOCK_RELEASE_BINARY))
+                .unwrap()
+                .into());
         }
         next.run(req, extensions).await
     }
 }
 
+fn has_mock_token(req: &Request) -> bool {
+    req.headers()
+        .get("Authorization")
+        .and_then(|token| token.to_str().ok())
+        .is_some_and(|token| token == format!("Bearer {}", MOCK_TOKEN))
+}
+
 fn generate_pet_status_animation() -> Animation {
     let fps = 30;
     let total_frames = 100;
@@ -208,21 +347,22 @@ fn generate_pet_status_animation() -> Animation {
     let mut last_jump_frame = 0;
     while curr_frame < total_frames {
         let delta_x_from_center = 0;
-        let delta_y_from_center = if curr_frame == 0 || curr_frame % 15 != 0 || total_jumps >= 3 {
-            if curr_frame > 1 && (curr_frame - last_jump_frame <= 3) {
-                if curr_frame - last_jump_frame == 1 {
-                    previous_delta_y_from_center - 1
+        let delta_y_from_center =
+            if curr_frame == 0 || !curr_frame.is_multiple_of(15) || total_jumps >= 3 {
+                if curr_frame > 1 && (curr_frame - last_jump_frame <= 3) {
+                    if curr_frame - last_jump_frame == 1 {
+                        previous_delta_y_from_center - 1
+                    } else {
+                        previous_delta_y_from_center
+                    }
                 } else {
-                    previous_delta_y_from_center
+                    std::cmp::min(0, previous_delta_y_from_center + 2)
                 }
             } else {
-                std::cmp::min(0, previous_delta_y_from_center + 2)
-            }
-        } else {
-            total_jumps += 1;
-            last_jump_frame = curr_frame;
-            previous_delta_y_from_center - 1
-        };
+                total_jumps += 1;
+                last_jump_frame = curr_frame;
+                previous_delta_y_from_center - 1
+            };
         let (image, colours) = get_pet_status_animation_for_frame(
             &PET,
             curr_frame,
@@ -238,12 +378,14 @@ fn generate_pet_status_animation() -> Animation {
         {
             curr_frame += 1;
         } else {
-            if previous_image.is_some() && previous_colours.is_some() {
+            if let (Some(previous_image), Some(previous_colours)) =
+                (previous_image, previous_colours)
+            {
                 windows.push(AnimationWindow {
                     start_frame_inclusive: curr_window_start,
                     end_frame_inclusive: curr_frame - 1,
-                    image: previous_image.unwrap(),
-                    colours: previous_colours.unwrap(),
+                    image: previous_image,
+                    colours: previous_colours,
                     delta_x_from_center: previous_delta_x_from_center,
                     delta_y_from_center: previous_delta_y_from_center,
                 });
@@ -265,7 +407,7 @@ fn generate_pet_status_animation() -> Animation {
         delta_y_from_center: previous_delta_y_from_center,
     });
 
-    return Animation { windows, fps };
+    Animation { windows, fps }
 }
 
 fn get_pet_status_animation_for_frame(
@@ -274,66 +416,16 @@ fn get_pet_status_animation_for_frame(
     is_in_the_air: bool,
     is_done_jumping: bool,
 ) -> (String, Vec<Vec<String>>) {
-    let ear_colour = "#0000ff";
-    let eye_colour = match pet.happiness {
-        h if h < 20.0 => "#ff0000", // red
-        h if h < 70.0 => "#0000ff", // blue
-        _ => "#00ff00",             // green
-    };
-    let mut eyes = match pet.happiness {
-        h if h < 20.0 => "x.x",
-        h if h < 70.0 => "o.o",
-        _ => "^.^",
-    };
-
-    if is_done_jumping {
-        eyes = if curr_frame % 40 == 0 && curr_frame != 0 {
-            "-.-"
-        } else {
-            eyes
-        };
-    }
+    let mut eyes_closed = is_done_jumping && curr_frame.is_multiple_of(40) && curr_frame != 0;
 
     if is_in_the_air {
-        eyes = "-.-";
+        eyes_closed = true;
     }
 
-    let tongue_colour = "#ff0000";
-    let tongue = match pet.hunger {
-        h if h < 20.0 => "U",
-        h if h < 70.0 => "-",
-        _ => "~",
-    };
-
-    let full_face = [
-        format!("/\\_/\\"),
-        format!("( {} )", eyes),
-        format!("=  {}  =", tongue),
-    ]
-    .join("\n");
-
-    let colours = vec![
-        vec![
-            ear_colour.to_string(),
-            ear_colour.to_string(),
-            ear_colour.to_string(),
-            ear_colour.to_string(),
-            "".to_string(),
-        ],
-        vec![
-            "".to_string(),
-            "".to_string(),
-            eye_colour.to_string(),
-            "".to_string(),
-            eye_colour.to_string(),
-        ],
-        vec![
-            "".to_string(),
-            "".to_string(),
-            "".to_string(),
-            tongue_colour.to_string(),
-        ],
-    ];
+    // A tired pet keeps its eyes closed
+    if pet.energy < 20.0 {
+        eyes_closed = true;
+    }
 
-    (full_face, colours)
+    theme::theme().render(pet, eyes_closed)
 }
@@ -0,0 +1,168 @@
+use http::Extensions;
+use reqwest::{Body, Request, Response};
+use reqwest_middleware::{Middleware, Next, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+
+/// Saves every request and its response to a directory (BITPET_HTTP_RECORD), so that a session
+/// can be replayed later with `ReplayMiddleware`
+pub struct RecordingMiddleware {
+    pub dir: PathBuf,
+}
+
+/// Answers requests with the responses saved by `RecordingMiddleware` (BITPET_HTTP_REPLAY),
+/// failing any request that was not recorded
+pub struct ReplayMiddleware {
+    pub dir: PathBuf,
+}
+
+/// One recorded request and its response. Request headers are not saved, so that tokens never
+/// end up on disk.
+#[derive(Serialize, Deserialize, Debug)]
+struct Recording {
+    method: String,
+    url: String,
+    request_body: String,
+    status: u16,
+    response_headers: Vec<(String, String)>,
+    response_body: String,
+}
+
+/// Recordings of the same request are numbered in the order they were made, so that a sequence
+/// (e.g. the pet's status before and after feeding) is replayed in the same order
+fn recording_key(req: &Request) -> String {
+    let body = req
+        .body()
+        .and_then(|body| body.as_bytes())
+        .unwrap_or_default();
+    let body_hash = format!("{:x}", Sha256::digest(body));
+    let path: String = req
+        .url()
+        .path()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("{}{}_{}", req.method(), path, &body_hash[..16])
+}
+
+fn recording_path(dir: &std::path::Path, key: &str, index: usize) -> PathBuf {
+    dir.join(format!("{}-{}.json", key, index))
+}
+
+fn middleware_error(message: String) -> reqwest_middleware::Error {
+    reqwest_middleware::Error::middleware(std::io::Error::other(message))
+}
+
+#[async_trait::async_trait]
+impl Middleware for RecordingMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let key = recording_key(&req);
+        let method = req.method().to_string();
+        let url = req.url().to_string();
+        let request_body = req
+            .body()
+            .and_then(|body| body.as_bytes())
+            .map(|body| String::from_utf8_lossy(body).to_string())
+            .unwrap_or_default();
+
+        let response = next.run(req, extensions).await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let response_body = response.bytes().await?;
+
+        let recording = Recording {
+            method,
+            url,
+            request_body,
+            status: status.as_u16(),
+            response_headers: headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+            response_body: String::from_utf8_lossy(&response_body).to_string(),
+        };
+        std::fs::create_dir_all(&self.dir).map_err(reqwest_middleware::Error::middleware)?;
+        let index = (0..)
+            .find(|index| !recording_path(&self.dir, &key, *index).exists())
+            .unwrap_or_default();
+        let content = serde_json::to_string_pretty(&recording)
+            .map_err(reqwest_middleware::Error::middleware)?;
+        std::fs::write(recording_path(&self.dir, &key, index), content)
+            .map_err(reqwest_middleware::Error::middleware)?;
+
+        // The body was consumed to record it, so the response is rebuilt
+        let mut rebuilt = http::Response::builder().status(status);
+        if let Some(rebuilt_headers) = rebuilt.headers_mut() {
+            *rebuilt_headers = headers;
+        }
+        Ok(rebuilt
+            .body(Body::from(response_body))
+            .map_err(reqwest_middleware::Error::middleware)?
+            .into())
+    }
+}
+
+/// How many times each recording key was replayed by this process
+static REPLAY_COUNTS: LazyLock<Mutex<HashMap<String, usize>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[async_trait::async_trait]
+impl Middleware for ReplayMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        _extensions: &mut Extensions,
+        _next: Next<'_>,
+    ) -> Result<Response> {
+        let key = recording_key(&req);
+        let index = {
+            let mut counts = REPLAY_COUNTS.lock().unwrap_or_else(|e| e.into_inner());
+            let count = counts.entry(key.clone()).or_default();
+            *count += 1;
+            *count - 1
+        };
+        // Once a sequence runs out, its last response keeps being served
+        let Some(path) = (0..=index)
+            .rev()
+            .map(|index| recording_path(&self.dir, &key, index))
+            .find(|path| path.exists())
+        else {
+            return Err(middleware_error(format!(
+                "No recorded response for {} {} in {}",
+                req.method(),
+                req.url(),
+                self.dir.display()
+            )));
+        };
+
+        let content =
+            std::fs::read_to_string(&path).map_err(reqwest_middleware::Error::middleware)?;
+        let recording: Recording =
+            serde_json::from_str(&content).map_err(reqwest_middleware::Error::middleware)?;
+        let mut response = http::Response::builder().status(recording.status);
+        for (name, value) in &recording.response_headers {
+            // The body is stored decoded, so its original length and encoding no longer apply
+            if name.eq_ignore_ascii_case("content-length")
+                || name.eq_ignore_ascii_case("content-encoding")
+                || name.eq_ignore_ascii_case("transfer-encoding")
+            {
+                continue;
+            }
+            response = response.header(name, value);
+        }
+        Ok(response
+            .body(Body::from(recording.response_body))
+            .map_err(reqwest_middleware::Error::middleware)?
+            .into())
+    }
+}
@@ -1,44 +1,104 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 
 mod auth;
 mod commands;
 mod config;
 mod constants;
+mod doctor;
 mod error;
+mod event_log;
+mod export;
 mod git;
 mod http_mocking;
+mod http_recording;
 mod pet;
+mod theme;
 mod ui;
+mod update;
 mod utils;
-use ui::{draw_animation_in_center_of_box, final_cleanup_for_terminal};
+use ui::draw_animation_in_center_of_box;
 extern crate ctrlc;
 extern crate reqwest;
 extern crate reqwest_middleware;
-use crate::http_mocking::MockingMiddleware;
 
-use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{IsTerminal, Write};
 
 use async_trait::async_trait;
 use auth::{AuthenticatedCommand, do_login, do_logout, execute_authenticated_command};
+use colored::Colorize;
 
 use commands::Commands;
 use config::{Config, UserInfo};
-use constants::UPDATE_CHECK_PATH;
 use pet::{
-    CommandIfPetExists, execute_command_if_pet_exists, feed_pet, get_pet_status, play_with_pet,
-    submit_challenge_answer,
+    CommandIfPetExists, create_pet, execute_command_if_pet_exists, feed_pet, get_pet_status,
+    play_with_pet, remove_pet, sleep_with_pet, submit_challenge_answer,
 };
 
 use crate::pet::FeedStatus;
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    after_help = "Exit codes:\n  0  Success\n  1  Generic error\n  2  Not logged in or session expired\n  3  Network error\n  4  Config or IO error\n  5  Git error\n\nShell completions (bash, zsh, fish, powershell or elvish), e.g. for bash:\n  pet completions bash > ~/.local/share/bash-completion/completions/pet"
+)]
 struct Args {
     #[command(subcommand)]
     command: Commands,
+
+    /// Path to the config file to use (overrides the BITPET_CONFIG env var)
+    #[arg(long, global = true, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    config: Option<std::path::PathBuf>,
+
+    /// Disable colored output (also disabled by the NO_COLOR env var, or when not printing to
+    /// a terminal)
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Only draw the final frame of animations (also enabled by BITPET_NO_ANIMATION=1)
+    #[arg(long, visible_alias = "static", global = true)]
+    no_animation: bool,
+
+    /// Multiplier for the speed of animations, between 0.1 and 4.0 (overrides the
+    /// animation_speed config)
+    #[arg(long, global = true, value_name = "MULTIPLIER")]
+    speed: Option<f32>,
+
+    /// Do not retry requests that fail with a network or server error
+    #[arg(long, global = true)]
+    no_retry: bool,
+
+    /// Give up on requests to the BitPet API after this many seconds (overrides the
+    /// request_timeout_ms config)
+    #[arg(long, global = true, value_name = "SECS", value_parser = clap::value_parser!(u64).range(1..=600))]
+    timeout: Option<u64>,
+
+    /// URL of the BitPet API (overrides the BITPET_API_URL env var and the api_base_url config)
+    #[arg(long, global = true, value_name = "URL")]
+    api_url: Option<String>,
+
+    /// Only print errors, prompts and --json output
+    #[arg(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also print request URLs, timings and other diagnostics to stderr
+    #[arg(long, short, global = true)]
+    verbose: bool,
+
+    /// Print command results as JSON instead of formatted text and animations
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Answer yes to confirmation prompts, e.g. for scripts
+    #[arg(long, short, global = true)]
+    yes: bool,
+
+    /// How to print errors to stderr
+    #[arg(long, global = true, value_enum, default_value_t = utils::ErrorFormat::Text)]
+    error_format: utils::ErrorFormat,
 }
 
 /// Result type for command operations
@@ -48,26 +108,52 @@ type CommandResult = Result<(), Box<dyn error::CustomErrorTrait>>;
 struct LogoutCommand;
 struct WhoamiCommand;
 struct StatusCommand;
-struct FeedCommand;
+struct FeedCommand {
+    since: Option<String>,
+    /// Only feed from this repo (--repo)
+    repo: Option<String>,
+    /// Whether to accept a challenge without asking (--accept-challenges/--decline-challenges)
+    challenge_choice: Option<bool>,
+}
 struct PlayCommand;
+struct SleepCommand;
+struct NewPetCommand {
+    name: Option<String>,
+}
+struct RemovePetCommand;
+struct ExportCommand {
+    format: export::ExportFormat,
+    out: std::path::PathBuf,
+}
 struct AddRepoCommand {
-    path: String,
+    paths: Vec<String>,
+    recursive: bool,
 }
 struct RemoveRepoCommand {
     path: String,
 }
-struct ListReposCommand;
+struct ListReposCommand {
+    with_commits: bool,
+}
 struct ChallengeReadCommand;
-struct ChallengeAnswerCommand;
-struct ChallengeRemoveCommand;
+struct ChallengeAnswerCommand {
+    id: Option<String>,
+}
+struct ChallengeRemoveCommand {
+    id: Option<String>,
+}
 
 // Command handlers
 
-async fn handle_login(config: &mut Config) -> CommandResult {
+async fn handle_login(config: &mut Config, no_browser: bool) -> CommandResult {
     if let Some(_user) = &config.user {
-        return Err(format!("You are already logged in with email: {}", _user.email).into());
+        Err(format!(
+            "You are already logged in with email: {}. To use another account, add a profile for it using 'pet account add'",
+            _user.email
+        )
+        .into())
     } else {
-        do_login(config).await
+        do_login(config, no_browser).await
     }
 }
 
@@ -85,95 +171,448 @@ async fn do_logout_impl(user: UserInfo, config: &mut Config) -> CommandResult {
 
 #[async_trait]
 impl AuthenticatedCommand for WhoamiCommand {
-    async fn execute(self, user: UserInfo, _config: &mut Config) -> CommandResult {
-        do_whoami_impl(user, _config).await
+    async fn execute(self, user: UserInfo, config: &mut Config) -> CommandResult {
+        do_whoami_impl(user, config).await
     }
 }
 
-async fn do_whoami_impl(user: UserInfo, _config: &mut Config) -> CommandResult {
-    println!("Email: {}", user.email);
-    println!("Username: {}", user.username);
+/// With --verbose, also shows what is useful for support: where the config is, the state of the
+/// login token (never the token itself), the API, the number of repos and the last update check
+async fn do_whoami_impl(user: UserInfo, config: &mut Config) -> CommandResult {
+    let verbose = utils::log_level() == utils::LogLevel::Verbose;
+    let last_update_check_time_ms =
+        Some(config.last_update_check_time_ms).filter(|time_ms| *time_ms > 0);
+    if ui::json_output_enabled() {
+        // The token is left out on purpose
+        let mut json = serde_json::json!({
+            "email": user.email,
+            "username": user.username,
+        });
+        if verbose {
+            json["config_path"] = config.path().display().to_string().into();
+            json["token_present"] = (!user.token.is_empty()).into();
+            json["token_expired"] = user.token_expired().into();
+            json["token_expires_at_ms"] = serde_json::json!(user.token_expires_at_ms);
+            json["refresh_token_present"] = user.refresh_token.is_some().into();
+            json["api_base_url"] = utils::get_api_base_url().into();
+            json["repos"] = config.repos.len().into();
+            json["last_update_check_time_ms"] = serde_json::json!(last_update_check_time_ms);
+        }
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+    utils::info!("Email: {}", user.email);
+    utils::info!("Username: {}", user.username);
+    if !verbose {
+        return Ok(());
+    }
+    utils::info!("Config: {}", config.path().display());
+    let token_status = match user.token_expires_at_ms {
+        _ if user.token.is_empty() => "missing".to_string(),
+        _ if user.token_expired() => "present, expired".to_string(),
+        Some(expires_at_ms) => format!("present, expires {}", format_local_time(expires_at_ms)),
+        None => "present".to_string(),
+    };
+    utils::info!("Token: {}", token_status);
+    utils::info!(
+        "Refresh token: {}",
+        if user.refresh_token.is_some() {
+            "present"
+        } else {
+            "none"
+        }
+    );
+    utils::info!("API: {}", utils::get_api_base_url());
+    utils::info!("Repos: {}", config.repos.len());
+    utils::info!(
+        "Last update check: {}",
+        last_update_check_time_ms
+            .map(format_local_time)
+            .unwrap_or_else(|| "never".to_string())
+    );
     Ok(())
 }
 
+/// A time in ms since the epoch as local time, e.g. "2025-09-19 20:12"
+fn format_local_time(time_ms: u128) -> String {
+    chrono::DateTime::from_timestamp_millis(time_ms as i64)
+        .map(|time| {
+            time.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
 #[async_trait]
 impl CommandIfPetExists for StatusCommand {
     async fn execute(self, _user: UserInfo, _config: &mut Config) -> CommandResult {
-        let pet = get_pet_status(_user.token.as_str(), _config).await?;
-        println!("{}", pet.0);
+        let pet = get_pet_status(_config).await?;
+        if ui::json_output_enabled() {
+            println!("{}", serde_json::to_string_pretty(&pet.0)?);
+            return Ok(());
+        }
+        utils::info!("{}", pet.0);
+        if let Some(warning) = streak_warning(_config, &pet.0).await {
+            utils::info!("{}", warning.yellow());
+        }
         draw_animation_in_center_of_box(&pet.1).await
     }
 }
 
+/// A warning for when the pet has a streak but was not fed today, so the streak ends unless it
+/// is. The repos are checked for commits made today, to tell whether there is anything to feed.
+async fn streak_warning(config: &Config, pet: &pet::Pet) -> Option<String> {
+    if pet.streak == 0 {
+        return None;
+    }
+    let midnight = chrono::Local::now()
+        .date_naive()
+        .and_hms_opt(0, 0, 0)?
+        .and_local_timezone(chrono::Local)
+        .earliest()?;
+    let midnight_ms = midnight.timestamp_millis() as u128;
+    if config
+        .last_feed_time_ms
+        .is_some_and(|last_feed| last_feed >= midnight_ms)
+    {
+        return None;
+    }
+
+    let (normalised_paths, _) = config.get_normalised_paths();
+    let since = format!("@{}", midnight.timestamp());
+    // Best effort: if the repos cannot be read, the warning just does not say which it is
+    let has_commits_today = collect_new_commits(config, normalised_paths, &since)
+        .await
+        .is_ok_and(|repos| repos.iter().any(|(_, _, commits)| !commits.is_empty()));
+    Some(if has_commits_today {
+        format!(
+            "⚠ Feed today to keep your {}-day streak! You have new commits to feed.",
+            pet.streak
+        )
+    } else {
+        format!("⚠ Feed today to keep your {}-day streak!", pet.streak)
+    })
+}
+
+/// Show the pet from the last successful status, for when the servers cannot be reached
+async fn show_cached_status(
+    config: &Config,
+    error: Box<dyn error::CustomErrorTrait>,
+) -> CommandResult {
+    let (Some(pet), Some(animation)) = (&config.cached_pet, &config.cached_status_animation) else {
+        return Err(error);
+    };
+    if ui::json_output_enabled() {
+        // stdout only gets the JSON, so that it can still be parsed
+        eprintln!("{}", "BitPet is offline — showing last known pet".yellow());
+        println!("{}", serde_json::to_string_pretty(pet)?);
+        return Ok(());
+    }
+    utils::info!("{}", "BitPet is offline — showing last known pet".yellow());
+    utils::info!("{}", pet);
+    draw_animation_in_center_of_box(animation).await
+}
+
 #[async_trait]
 impl CommandIfPetExists for FeedCommand {
     async fn execute(self, user: UserInfo, config: &mut Config) -> CommandResult {
-        feed_impl(user, config).await
+        feed_impl(user, config, self.since, self.repo, self.challenge_choice).await
     }
 }
 
-async fn feed_impl(_user: UserInfo, config: &mut Config) -> CommandResult {
-    let normalised_paths = config.get_valid_normalised_paths_and_save()?;
-    if normalised_paths.is_empty() {
-        println!("No Git repositories added yet!");
-        return Ok(());
+/// Ask whether to accept the challenge the pet asked for. Without a terminal to ask in (or if
+/// stdin is closed), the challenge_prompt_default config decides.
+fn ask_to_accept_challenge(config: &Config) -> Result<bool, Box<dyn error::CustomErrorTrait>> {
+    let default = config.challenge_prompt_default == config::ChallengePromptDefault::Accept;
+    if !std::io::stdin().is_terminal() {
+        return Ok(default);
     }
+    println!("Your pet is asking for a coding challenge! Do you accept (Y/n)?");
+    loop {
+        let mut input = String::new();
+        if std::io::stdin().read_line(&mut input)? == 0 {
+            return Ok(default);
+        }
+        match input.trim().to_lowercase().as_str() {
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Invalid input! Please enter Y or n"),
+        }
+    }
+}
 
-    let mut commits: HashMap<String, Vec<git::Commit>> = HashMap::new();
+/// The available repos to look for commits in, and the unavailable ones: all of the config's,
+/// or only `repo` if given, which must be one of them
+fn repos_to_feed_from(
+    config: &Config,
+    repo: Option<String>,
+) -> Result<(Vec<utils::NormalisedGitPath>, Vec<String>), Box<dyn error::CustomErrorTrait>> {
+    let (normalised_paths, unavailable_repos) = config.get_normalised_paths();
+    let Some(repo) = repo else {
+        return Ok((normalised_paths, unavailable_repos));
+    };
+    let repo = utils::NormalisedGitPath::new(repo)?;
+    if !normalised_paths.contains(&repo) {
+        return Err(format!(
+            "{} is not one of your repos. Add it first with: pet add-repo {}",
+            repo, repo
+        )
+        .into());
+    }
+    Ok((vec![repo], Vec::new()))
+}
 
+/// The start of the commit window: --since, or else the last feed, or else the feed_window
+fn feed_since(config: &Config, since: Option<String>) -> String {
+    // git reads "@<seconds>" as a unix timestamp
+    since.unwrap_or_else(|| match config.last_feed_time_ms {
+        Some(last_feed_time_ms) => format!("@{}", last_feed_time_ms / 1000),
+        None => config.feed_window.clone(),
+    })
+}
+
+/// The key (see `utils::repo_key`) and the commits of each available repo since `since` that
+/// were not fed already, in the order of the repos. A repo listed twice is only looked at once,
+/// and ignored repos (see `git::is_ignored`) not at all.
+async fn collect_new_commits(
+    config: &Config,
+    normalised_paths: Vec<utils::NormalisedGitPath>,
+    since: &str,
+) -> Result<
+    Vec<(utils::NormalisedGitPath, String, Vec<git::Commit>)>,
+    Box<dyn error::CustomErrorTrait>,
+> {
+    git::ensure_git_available()?;
+    let mut repos = Vec::new();
+    let mut seen_keys = HashSet::new();
     for repo in normalised_paths {
-        let _commits = git::get_commits_for_path_since(&repo, "1week")?;
-        commits.insert(
-            format!("{:x}", Sha256::digest(repo.to_string().as_bytes())),
-            _commits,
+        if git::is_ignored(&repo) {
+            utils::verbose!("Skipping {}, which has a {} file", repo, git::IGNORE_FILE);
+            continue;
+        }
+        let repo_key = utils::repo_key(&repo);
+        if seen_keys.insert(repo_key.clone()) {
+            repos.push((repo, repo_key));
+        } else {
+            eprintln!(
+                "{}",
+                format!(
+                    "Warning: {} is listed more than once in your repos, so its commits are only counted once.",
+                    repo
+                )
+                .yellow()
+            );
+        }
+    }
+
+    let identities = git::user_identities(&config.extra_author_emails)?;
+    // Run git for all repos at once, since each call blocks on a separate process
+    let lookups: Vec<_> = repos
+        .into_iter()
+        .map(|(repo, repo_key)| {
+            let since = since.to_string();
+            let identities = identities.clone();
+            let require_signed = config.require_signed;
+            tokio::task::spawn_blocking(move || {
+                let result =
+                    git::get_commits_for_path_since(&repo, &since, &identities, require_signed);
+                (repo, repo_key, result)
+            })
+        })
+        .collect();
+
+    let mut new_commits = Vec::new();
+    for lookup in lookups {
+        let (repo, repo_key, result) = lookup
+            .await
+            .map_err(|e| format!("Failed to fetch git commits: {}", e))?;
+        let last_fed_commit_time_ms = config.last_fed_commit_time_ms.get(&repo_key).copied();
+        let commits: Vec<git::Commit> = result?
+            .into_iter()
+            .filter(|commit| {
+                last_fed_commit_time_ms
+                    .is_none_or(|last_fed| commit.time_since_epoch_ms() > last_fed)
+            })
+            .collect();
+        utils::verbose!(
+            "{}: {} new commit(s) since {}{}",
+            repo,
+            commits.len(),
+            since,
+            identity_breakdown(&commits, &identities)
         );
+        new_commits.push((repo, repo_key, commits));
+    }
+    Ok(new_commits)
+}
+
+/// How many of the commits each identity authored, e.g. " (3 as Jo <jo@x.com>, 1 as
+/// <bot@x.com>)", or nothing when there is only one identity to tell apart
+fn identity_breakdown(commits: &[git::Commit], identities: &[git::Identity]) -> String {
+    if identities.len() < 2 || commits.is_empty() {
+        return String::new();
     }
+    let counts: Vec<String> = identities
+        .iter()
+        .enumerate()
+        .filter_map(|(i, identity)| {
+            let count = commits
+                .iter()
+                .filter(|commit| commit.identity() == i)
+                .count();
+            (count > 0).then(|| format!("{} as {}", count, identity))
+        })
+        .collect();
+    format!(" ({})", counts.join(", "))
+}
 
-    let feed_result = feed_pet(_user.token.as_str(), config, commits).await?;
+async fn feed_dry_run_impl(
+    config: &Config,
+    since: Option<String>,
+    repo: Option<String>,
+) -> CommandResult {
+    let (normalised_paths, unavailable_repos) = repos_to_feed_from(config, repo)?;
+    let since = feed_since(config, since);
+    let new_commits = collect_new_commits(config, normalised_paths, &since).await?;
 
-    config.challenge = None;
+    if ui::json_output_enabled() {
+        let repos: Vec<serde_json::Value> = new_commits
+            .iter()
+            .map(|(repo, _, commits)| {
+                serde_json::json!({
+                    "path": repo.to_string(),
+                    "commits": commits,
+                })
+            })
+            .collect();
+        let json = serde_json::json!({
+            "since": since,
+            "repos": repos,
+            "unavailable_repos": unavailable_repos,
+        });
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+
+    if new_commits.is_empty() && unavailable_repos.is_empty() {
+        utils::info!("No Git repositories added yet!");
+        return Ok(());
+    }
+    utils::info!(
+        "Commits that would be fed (since {}), without feeding your pet:",
+        since
+    );
+    let identities = git::user_identities(&config.extra_author_emails)?;
+    for (repo, _, commits) in &new_commits {
+        utils::info!(
+            "\n{} ({} commits){}",
+            repo.to_string().bold(),
+            commits.len(),
+            identity_breakdown(commits, &identities).dimmed()
+        );
+        for commit in commits {
+            utils::info!(
+                "  {}  {}",
+                &commit.hash()[..commit.hash().len().min(10)],
+                format_local_time(commit.time_since_epoch_ms() as u128)
+            );
+        }
+    }
+    if !unavailable_repos.is_empty() {
+        utils::info!("{}", "\nSkipped, since they are not available:".dimmed());
+        for repo in unavailable_repos {
+            utils::info!("{}", format!("- {}", repo).dimmed());
+        }
+    }
+    Ok(())
+}
+
+async fn feed_impl(
+    _user: UserInfo,
+    config: &mut Config,
+    since: Option<String>,
+    repo: Option<String>,
+    challenge_choice: Option<bool>,
+) -> CommandResult {
+    // Feeding from one repo leaves the others' commits for the next feed
+    let all_repos = repo.is_none();
+    let (normalised_paths, unavailable_repos) = repos_to_feed_from(config, repo)?;
+    for repo in unavailable_repos {
+        utils::verbose!("Skipping {}, which is not available", repo);
+    }
+    if normalised_paths.is_empty() {
+        utils::info!("No Git repositories added yet!");
+        return Ok(());
+    }
+
+    let since = feed_since(config, since);
+    let feed_time_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+
+    let mut commits: HashMap<String, Vec<git::Commit>> = HashMap::new();
+    let mut newest_commit_times: HashMap<String, u64> = HashMap::new();
+    for (_, repo_key, repo_commits) in collect_new_commits(config, normalised_paths, &since).await?
+    {
+        if let Some(newest) = repo_commits.iter().map(|c| c.time_since_epoch_ms()).max() {
+            newest_commit_times.insert(repo_key.clone(), newest);
+        }
+        commits.insert(repo_key, repo_commits);
+    }
+
+    let feed_result = feed_pet(config, commits).await?;
+    event_log::log_event("feed", &feed_result.status, feed_result.pet.as_ref());
+
+    if let FeedStatus::FeedSuccess = feed_result.status {
+        if all_repos {
+            config.last_feed_time_ms = Some(feed_time_ms);
+        }
+        config.last_fed_commit_time_ms.extend(newest_commit_times);
+    }
     config.save()?;
 
     match feed_result.status {
         FeedStatus::AskForChallenge => {
-            println!("Your pet is asking for a coding challenge! Do you accept (Y/n)?");
-            let mut accepted = false;
-            loop {
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input).unwrap();
-                if input.trim() == "Y" {
-                    accepted = true;
-                    break;
-                } else if input.trim() == "n" {
-                    break;
-                }
-                println!("Invalid input! Please enter Y or n");
-            }
+            let accepted = match challenge_choice {
+                Some(accepted) => accepted,
+                None => ask_to_accept_challenge(config)?,
+            };
             if accepted {
-                config.challenge = feed_result.challenge.clone();
-                config.save()?;
+                if let Some(challenge) = feed_result.challenge.clone() {
+                    config.add_challenge(challenge);
+                    config.save()?;
+                }
                 if let Some(text_before_animation) = feed_result.text_before_animation {
-                    println!("{}", text_before_animation);
+                    utils::info!("{}", text_before_animation);
                 }
                 if let Some(animation) = feed_result.animation {
                     draw_animation_in_center_of_box(&animation).await?;
                 }
-                println!("{}", feed_result.challenge.unwrap());
-                println!("Please answer the challenge by typing 'pet challenge ans'");
+                if let Some(challenge) = feed_result.challenge {
+                    utils::info!("{}", challenge);
+                    utils::info!(
+                        "Please answer the challenge by typing 'pet challenge ans {}'",
+                        challenge.id
+                    );
+                }
             } else {
-                println!("You declined a challenge, and did not feed your pet!");
+                utils::info!("You declined a challenge, and did not feed your pet!");
             }
             Ok(())
         }
         _ => {
             if let Some(text_before_animation) = feed_result.text_before_animation {
-                println!("{}", text_before_animation);
+                utils::info!("{}", text_before_animation);
             }
             if let Some(animation) = feed_result.animation {
                 draw_animation_in_center_of_box(&animation).await?;
             }
             if let Some(pet) = feed_result.pet {
-                println!("{}", pet);
+                config.record_stats(&pet);
+                config.save()?;
+                utils::info!("{}", pet);
             }
             Ok(())
         }
@@ -182,39 +621,216 @@ async fn feed_impl(_user: UserInfo, config: &mut Config) -> CommandResult {
 
 #[async_trait]
 impl CommandIfPetExists for PlayCommand {
-    async fn execute(self, _user: UserInfo, _config: &mut Config) -> CommandResult {
-        let response = play_with_pet(_user.token.as_str(), _config).await?;
+    async fn execute(self, _user: UserInfo, config: &mut Config) -> CommandResult {
+        let response = play_with_pet(config).await?;
+        event_log::log_event("play", &response.status, response.pet.as_ref());
         if let Some(text_before_animation) = response.text_before_animation {
-            println!("{}", text_before_animation);
+            utils::info!("{}", text_before_animation);
         }
         if let Some(animation) = response.animation {
             draw_animation_in_center_of_box(&animation).await?;
         }
         if let Some(pet) = response.pet {
-            println!("{}", pet);
+            config.record_stats(&pet);
+            config.save()?;
+            utils::info!("{}", pet);
         }
         Ok(())
     }
 }
 
 #[async_trait]
-impl CommandIfPetExists for AddRepoCommand {
+impl CommandIfPetExists for SleepCommand {
+    async fn execute(self, _user: UserInfo, config: &mut Config) -> CommandResult {
+        let response = sleep_with_pet(config).await?;
+        event_log::log_event("sleep", &response.status, response.pet.as_ref());
+        if let Some(text_before_animation) = response.text_before_animation {
+            utils::info!("{}", text_before_animation);
+        }
+        if let Some(animation) = response.animation {
+            draw_animation_in_center_of_box(&animation).await?;
+        }
+        if let Some(pet) = response.pet {
+            config.record_stats(&pet);
+            config.save()?;
+            utils::info!("{}", pet);
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl AuthenticatedCommand for NewPetCommand {
+    async fn execute(self, _user: UserInfo, config: &mut Config) -> CommandResult {
+        new_pet_impl(config, self.name).await
+    }
+}
+
+async fn new_pet_impl(config: &mut Config, name: Option<String>) -> CommandResult {
+    let name = match name {
+        Some(name) => name,
+        None => {
+            println!("What would you like to name your pet?");
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input)?;
+            input
+        }
+    };
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Your pet needs a name!".into());
+    }
+
+    let response = create_pet(config, name).await?;
+    if let Some(text_before_animation) = response.text_before_animation {
+        utils::info!("{}", text_before_animation);
+    }
+    if let Some(animation) = response.animation {
+        draw_animation_in_center_of_box(&animation).await?;
+    }
+    utils::info!("{}", response.pet);
+    Ok(())
+}
+
+#[async_trait]
+impl CommandIfPetExists for RemovePetCommand {
     async fn execute(self, _user: UserInfo, config: &mut Config) -> CommandResult {
-        add_repo_impl(self.path, config).await
+        remove_pet_impl(config).await
     }
 }
 
-async fn add_repo_impl(path: String, config: &mut Config) -> CommandResult {
-    let normalised_path = utils::NormalisedGitPath::new(path)?;
+async fn remove_pet_impl(config: &mut Config) -> CommandResult {
+    let (pet, _animation) = get_pet_status(config).await?;
 
-    if config.repos.contains(&normalised_path.to_string()) {
-        return Err(format!("Repo already added: {}", normalised_path).into());
+    let confirmed = utils::confirm_by_typing(
+        &format!(
+            "This will permanently delete {}. Type the pet's name to confirm:",
+            pet.name
+        ),
+        &pet.name,
+    )?;
+    if !confirmed {
+        utils::info!("The name did not match, so {} was not deleted.", pet.name);
+        return Ok(());
     }
 
-    config.repos.push(normalised_path.to_string());
+    remove_pet(config).await?;
+
+    // Everything stored about the pet and its feeding belonged to the old pet
+    config.cached_pet = None;
+    config.cached_status_animation = None;
+    config.stats_history.clear();
+    config.challenges.clear();
+    config.last_feed_time_ms = None;
+    config.last_fed_commit_time_ms.clear();
     config.save()?;
 
-    println!("Added new Git repository successfully!");
+    utils::info!("Goodbye, {}! Thanks for all the commits.", pet.name);
+    Ok(())
+}
+
+#[async_trait]
+impl CommandIfPetExists for ExportCommand {
+    async fn execute(self, _user: UserInfo, config: &mut Config) -> CommandResult {
+        export_impl(config, self.format, self.out).await
+    }
+}
+
+async fn export_impl(
+    config: &mut Config,
+    format: export::ExportFormat,
+    out: std::path::PathBuf,
+) -> CommandResult {
+    let (_pet, animation) = get_pet_status(config).await?;
+
+    let mut file = std::io::BufWriter::new(std::fs::File::create(&out)?);
+    export::export_animation(&animation, format, &mut file)?;
+    file.flush()?;
+
+    utils::info!("Exported your pet to {}", out.display());
+    Ok(())
+}
+
+#[async_trait]
+impl CommandIfPetExists for AddRepoCommand {
+    async fn execute(self, _user: UserInfo, config: &mut Config) -> CommandResult {
+        add_repo_impl(self.paths, self.recursive, config).await
+    }
+}
+
+async fn add_repo_impl(paths: Vec<String>, recursive: bool, config: &mut Config) -> CommandResult {
+    let mut added = 0;
+    let mut already_added = 0;
+    let mut skipped = 0;
+    for pattern in paths {
+        let expanded = utils::expand_glob(&pattern);
+        if expanded.is_empty() {
+            utils::info!(
+                "{}",
+                format!("Skipped {}: no paths match", pattern).yellow()
+            );
+            skipped += 1;
+        }
+        let mut repo_paths = Vec::new();
+        for path in expanded {
+            let path_buf = std::path::PathBuf::from(&path);
+            if recursive && path_buf.is_dir() && !git::is_git(&path_buf) {
+                let found = git::find_git_repos(&path_buf, git::MAX_REPO_SEARCH_DEPTH);
+                if found.is_empty() {
+                    utils::info!(
+                        "{}",
+                        format!(
+                            "Skipped {}: no Git repositories found (searched {} levels deep)",
+                            path,
+                            git::MAX_REPO_SEARCH_DEPTH
+                        )
+                        .yellow()
+                    );
+                    skipped += 1;
+                }
+                repo_paths.extend(found.into_iter().map(|repo| repo.display().to_string()));
+            } else {
+                repo_paths.push(path);
+            }
+        }
+
+        // One bad path does not stop the others from being added
+        for path in repo_paths {
+            match utils::NormalisedGitPath::new(path.clone()) {
+                Ok(normalised_path) => {
+                    if config.add_repo(&normalised_path) {
+                        utils::info!("Added {}", normalised_path);
+                        added += 1;
+                    } else {
+                        utils::info!(
+                            "{}",
+                            format!("Skipped {}: already added", normalised_path).yellow()
+                        );
+                        already_added += 1;
+                    }
+                }
+                Err(e) => {
+                    utils::info!("{}", format!("Skipped {}: {}", path, e).yellow());
+                    skipped += 1;
+                }
+            }
+        }
+    }
+
+    if added == 0 {
+        return Err("No Git repositories were added".into());
+    }
+    config.save()?;
+    if already_added == 0 && skipped == 0 && added == 1 {
+        utils::info!("Added new Git repository successfully!");
+    } else {
+        utils::info!(
+            "Added {} Git repositories ({} already added, {} skipped)",
+            added,
+            already_added,
+            skipped
+        );
+    }
     Ok(())
 }
 
@@ -226,46 +842,140 @@ impl CommandIfPetExists for RemoveRepoCommand {
 }
 
 async fn remove_repo_impl(path: String, config: &mut Config) -> CommandResult {
-    let repo_path = match utils::NormalisedGitPath::new(path) {
-        Ok(normalised_path) => normalised_path.to_string(),
-        Err(utils::NormalisedPathError::PathNotGitRepository(path, _)) => path,
-        Err(e) => return Err(e.into()),
+    let Some(repo_path) = config.find_repo(path)? else {
+        utils::info!("Repository was never registered with BitPet, so nothing to remove!");
+        return Ok(());
     };
 
-    if !config.repos.contains(&repo_path) {
-        println!("Repository was never registered with BitPet, so nothing to remove!");
+    if !utils::confirm(&format!(
+        "Commits from {} will no longer feed your pet. Remove it?",
+        repo_path
+    ))? {
+        utils::info!("The repository was not removed.");
         return Ok(());
     }
 
-    if config.repos.contains(&repo_path) {
-        config
-            .repos
-            .remove(config.repos.iter().position(|r| r == &repo_path).unwrap());
-        config.save()?;
-    }
-    println!("Removed repository successfully!");
+    config
+        .repos
+        .remove(config.repos.iter().position(|r| r == &repo_path).unwrap());
+    config.save()?;
+    utils::info!("Removed repository successfully!");
     Ok(())
 }
 
 #[async_trait]
 impl CommandIfPetExists for ListReposCommand {
     async fn execute(self, _user: UserInfo, config: &mut Config) -> CommandResult {
-        list_repos_impl(config).await
+        list_repos_impl(config, self.with_commits).await
     }
 }
 
-async fn list_repos_impl(config: &mut Config) -> CommandResult {
-    let normalised_paths = config.get_valid_normalised_paths_and_save()?;
+async fn list_repos_impl(config: &mut Config, with_commits: bool) -> CommandResult {
+    let (normalised_paths, unavailable_repos) = config.get_normalised_paths();
+    let (ignored_repos, normalised_paths): (Vec<_>, Vec<_>) =
+        normalised_paths.into_iter().partition(git::is_ignored);
+    // With the number of commits the next feed would count, if asked for. Ignored repos come
+    // last.
+    let mut repos: Vec<(utils::NormalisedGitPath, Option<usize>)> = if with_commits {
+        let since = feed_since(config, None);
+        collect_new_commits(config, normalised_paths, &since)
+            .await?
+            .into_iter()
+            .map(|(repo, _, commits)| (repo, Some(commits.len())))
+            .collect()
+    } else {
+        normalised_paths
+            .into_iter()
+            .map(|repo| (repo, None))
+            .collect()
+    };
+    repos.extend(ignored_repos.into_iter().map(|repo| (repo, None)));
+    let repo_name = |repo: &utils::NormalisedGitPath| {
+        repo.path()
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| repo.to_string())
+    };
 
-    if normalised_paths.is_empty() {
-        println!("No Git repositories added yet");
+    if ui::json_output_enabled() {
+        let json: Vec<serde_json::Value> = repos
+            .iter()
+            .map(|(repo, commit_count)| {
+                if with_commits {
+                    serde_json::json!({
+                        "name": repo_name(repo),
+                        "path": repo.to_string(),
+                        "new_commits": commit_count,
+                        "ignored": git::is_ignored(repo),
+                    })
+                } else {
+                    serde_json::json!(repo.to_string())
+                }
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+
+    if repos.is_empty() && unavailable_repos.is_empty() {
+        utils::info!("No Git repositories added yet");
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = repos
+        .iter()
+        .map(|(repo, commit_count)| {
+            let mut row = vec![repo_name(repo), repo.to_string()];
+            match commit_count {
+                _ if git::is_ignored(repo) => row.push("(ignored)".to_string()),
+                Some(1) => row.push("(1 new commit)".to_string()),
+                Some(count) => row.push(format!("({} new commits)", count)),
+                None => {}
+            }
+            row
+        })
+        .collect();
+    for ((repo, commit_count), line) in repos.iter().zip(ui::format_table(&rows)) {
+        // Repos without anything to feed are dimmed
+        if *commit_count == Some(0) || git::is_ignored(repo) {
+            utils::info!("{}", line.dimmed());
+        } else {
+            utils::info!("{}", line);
+        }
+    }
+    // These may only be missing for now (e.g. on a drive that is not mounted), so they are kept
+    if !unavailable_repos.is_empty() {
+        utils::info!(
+            "{}",
+            "\nUnavailable (run 'pet repos prune' to remove them):".dimmed()
+        );
+        for repo in unavailable_repos {
+            utils::info!("{}", format!("- {}", repo).dimmed());
+        }
+    }
+
+    Ok(())
+}
+
+fn repos_prune_impl(config: &mut Config) -> CommandResult {
+    let (_, unavailable_repos) = config.get_normalised_paths();
+    if unavailable_repos.is_empty() {
+        utils::info!("All repositories are available, so there is nothing to prune");
         return Ok(());
     }
 
-    for normalised_path in normalised_paths {
-        println!("- {}", normalised_path);
+    utils::info!("These repositories no longer exist or are not Git repositories:");
+    for repo in &unavailable_repos {
+        utils::info!("- {}", repo);
+    }
+    if !utils::confirm("Remove them?")? {
+        utils::info!("No repositories were removed.");
+        return Ok(());
     }
 
+    let removed = config.prune_repos();
+    config.save()?;
+    utils::info!("Removed {} repositories", removed.len());
     Ok(())
 }
 
@@ -276,26 +986,75 @@ impl AuthenticatedCommand for ChallengeReadCommand {
     }
 }
 
+const NO_CHALLENGE_MESSAGE: &str =
+    "No challenge found! Type 'pet feed' and you may get a new challenge!";
+
 async fn challenge_read_impl(_user: UserInfo, config: &mut Config) -> CommandResult {
-    if let Some(challenge) = config.challenge.clone() {
-        println!("{}", challenge);
+    if config.challenges.is_empty() {
+        utils::info!("{}", NO_CHALLENGE_MESSAGE.red());
     } else {
-        println!(
-            "\x1b[31mNo challenge found! Type 'pet feed' and you may get a new challenge!\x1b[0m"
-        );
+        for challenge in &config.challenges {
+            utils::info!("{}", challenge);
+        }
     }
     Ok(())
 }
 
+/// Find the pending challenge to act on. Without an ID, the only pending challenge is used, or
+/// the user is asked to pick one if there are several (unless `prompt` is false).
+fn select_challenge(
+    config: &Config,
+    id: Option<String>,
+    prompt: bool,
+) -> Result<Option<pet::Challenge>, Box<dyn error::CustomErrorTrait>> {
+    let id = match (id, config.challenges.as_slice()) {
+        (_, []) => return Ok(None),
+        (Some(id), _) => id,
+        (None, [challenge]) => return Ok(Some(challenge.clone())),
+        (None, challenges) => {
+            let ids = challenges
+                .iter()
+                .map(|challenge| challenge.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            if !prompt {
+                return Err(format!(
+                    "There are several pending challenges, please give the ID of one: {}",
+                    ids
+                )
+                .into());
+            }
+            println!("Which challenge do you want to answer? ({})", ids);
+            let mut input = String::new();
+            std::io::stdin().read_line(&mut input)?;
+            input.trim().to_string()
+        }
+    };
+    match config
+        .challenges
+        .iter()
+        .find(|challenge| challenge.id == id)
+    {
+        Some(challenge) => Ok(Some(challenge.clone())),
+        None => Err(format!("No pending challenge with ID: {}", id).into()),
+    }
+}
+
 #[async_trait]
 impl AuthenticatedCommand for ChallengeAnswerCommand {
     async fn execute(self, _user: UserInfo, config: &mut Config) -> CommandResult {
-        challenge_answer_impl(_user, config).await
+        challenge_answer_impl(_user, config, self.id).await
     }
 }
 
-async fn challenge_answer_impl(_user: UserInfo, config: &mut Config) -> CommandResult {
-    if let Some(challenge) = config.challenge.clone() {
+async fn challenge_answer_impl(
+    _user: UserInfo,
+    config: &mut Config,
+    id: Option<String>,
+) -> CommandResult {
+    // Bound first, as the error in the temporary Result is not Send
+    let challenge = select_challenge(config, id, true)?;
+    if let Some(challenge) = challenge {
         println!("{}", challenge);
         let response = match challenge.answer_type {
             pet::ChallengeAnswerType::File => {
@@ -303,40 +1062,63 @@ async fn challenge_answer_impl(_user: UserInfo, config: &mut Config) -> CommandR
                 let mut input = String::new();
                 std::io::stdin().read_line(&mut input)?;
                 let path = input.trim().to_string();
-                let file_content = std::fs::read_to_string(path)?;
-                submit_challenge_answer(_user.token.as_str(), config, challenge.id, file_content)
-                    .await?
+                let file_content = pet::read_answer_file(&path, config.max_answer_size_bytes)?;
+                submit_challenge_answer(config, challenge.id.clone(), file_content).await?
             }
             pet::ChallengeAnswerType::Text => {
                 println!("Please enter the text you want to submit:");
                 let mut input = String::new();
                 std::io::stdin().read_line(&mut input)?;
                 let text = input.trim().to_string();
-                submit_challenge_answer(_user.token.as_str(), config, challenge.id, text).await?
+                submit_challenge_answer(config, challenge.id.clone(), text).await?
             }
         };
+        event_log::log_event(
+            "challenge-answer",
+            &response.status,
+            response
+                .feed_result
+                .as_ref()
+                .and_then(|feed_result| feed_result.pet.as_ref()),
+        );
 
         match response.status {
             pet::ChallengeAnswerStatus::Correct => {
-                let feed_result = response.feed_result.unwrap();
+                config.remove_challenge(&challenge.id);
+                config.save()?;
+                // The server may accept an answer without feeding the pet
+                let Some(feed_result) = response.feed_result else {
+                    utils::info!("Correct answer! The challenge is complete.");
+                    return Ok(());
+                };
                 if let Some(text_before_animation) = feed_result.text_before_animation {
-                    println!("{}", text_before_animation);
+                    utils::info!("{}", text_before_animation);
                 }
                 if let Some(animation) = feed_result.animation {
                     draw_animation_in_center_of_box(&animation).await?;
                 }
                 if let Some(pet) = feed_result.pet {
-                    println!("{}", pet);
+                    config.record_stats(&pet);
+                    config.save()?;
+                    utils::info!("{}", pet);
                 }
             }
             pet::ChallengeAnswerStatus::Incorrect => {
-                println!("\x1b[31mIncorrect answer! Please try again!\x1b[0m");
+                // The server no longer accepts answers to it
+                if response.attempts_remaining == Some(0) {
+                    config.remove_challenge(&challenge.id);
+                    config.save()?;
+                }
+                return Err(pet::ChallengeAnswerError::Incorrect(
+                    response.feedback,
+                    response.attempts_remaining,
+                    error::capture_backtrace(),
+                )
+                .into());
             }
         }
     } else {
-        println!(
-            "\x1b[31mNo challenge found! Type 'pet feed' and you may get a new challenge!\x1b[0m"
-        );
+        utils::info!("{}", NO_CHALLENGE_MESSAGE.red());
     }
     Ok(())
 }
@@ -344,119 +1126,381 @@ async fn challenge_answer_impl(_user: UserInfo, config: &mut Config) -> CommandR
 #[async_trait]
 impl AuthenticatedCommand for ChallengeRemoveCommand {
     async fn execute(self, _user: UserInfo, config: &mut Config) -> CommandResult {
-        challenge_remove_impl(_user, config).await
+        challenge_remove_impl(_user, config, self.id).await
     }
 }
 
-async fn challenge_remove_impl(_user: UserInfo, config: &mut Config) -> CommandResult {
-    if let Some(_) = config.challenge.clone() {
-        config.challenge = None;
+async fn challenge_remove_impl(
+    _user: UserInfo,
+    config: &mut Config,
+    id: Option<String>,
+) -> CommandResult {
+    if let Some(challenge) = select_challenge(config, id, false)? {
+        config.remove_challenge(&challenge.id);
         config.save()?;
-        println!("Removed challenge successfully!");
+        utils::info!("Removed challenge successfully!");
     } else {
-        println!(
-            "\x1b[31mNo challenge found! Type 'pet feed' and you may get a new challenge!\x1b[0m"
+        utils::info!("{}", NO_CHALLENGE_MESSAGE.red());
+    }
+    Ok(())
+}
+
+fn history_impl(config: &Config, days: u32) -> CommandResult {
+    const DAY_MS: u128 = 1000 * 60 * 60 * 24;
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    let start_ms = now_ms.saturating_sub(DAY_MS * days as u128);
+    let snapshots: Vec<&config::StatSnapshot> = config
+        .stats_history
+        .iter()
+        .filter(|snapshot| snapshot.time_ms >= start_ms && snapshot.time_ms <= now_ms)
+        .collect();
+
+    if ui::json_output_enabled() {
+        println!("{}", serde_json::to_string_pretty(&snapshots)?);
+        return Ok(());
+    }
+    if snapshots.is_empty() {
+        utils::info!(
+            "No history for the last {} days yet! Your pet's stats are recorded whenever you run 'pet status', 'pet feed' or 'pet play'.",
+            days
+        );
+        return Ok(());
+    }
+
+    // One character per day, showing the last snapshot of that day
+    let mut daily: Vec<Option<&config::StatSnapshot>> = vec![None; days as usize];
+    for snapshot in &snapshots {
+        let day = (((snapshot.time_ms - start_ms) / DAY_MS) as usize).min(days as usize - 1);
+        daily[day] = Some(snapshot);
+    }
+
+    type Stat = fn(&config::StatSnapshot) -> f64;
+    let stats: [(&str, Stat); 4] = [
+        ("Level", |snapshot| snapshot.level),
+        ("Hunger", |snapshot| snapshot.hunger),
+        ("Happiness", |snapshot| snapshot.happiness),
+        ("Energy", |snapshot| snapshot.energy),
+    ];
+    utils::info!("Your pet over the last {} days (oldest first):", days);
+    for (name, stat) in stats {
+        let values: Vec<Option<f64>> = daily.iter().map(|s| s.map(stat)).collect();
+        // Levels have no upper bound, so they are scaled to the range shown
+        let (min, max) = if name == "Level" {
+            values
+                .iter()
+                .flatten()
+                .fold((f64::MAX, f64::MIN), |(min, max), value| {
+                    (min.min(*value), max.max(*value))
+                })
+        } else {
+            (0.0, 100.0)
+        };
+        let first = stat(snapshots[0]);
+        let last = stat(snapshots[snapshots.len() - 1]);
+        utils::info!(
+            "{:<10} {}  {:.1} -> {:.1}",
+            name,
+            ui::sparkline(&values, min, max),
+            first,
+            last
         );
     }
     Ok(())
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct UpdateCheckAPIResult {
-    pub update_available: bool,
+fn account_list_impl(config: &Config) -> CommandResult {
+    let mut profiles: Vec<(&str, Option<&UserInfo>)> = config
+        .profiles
+        .iter()
+        .map(|(name, profile)| (name.as_str(), profile.user.as_ref()))
+        .collect();
+    profiles.push((config.active_profile.as_str(), config.user.as_ref()));
+    profiles.sort_by_key(|(name, _)| *name);
+
+    if ui::json_output_enabled() {
+        let json: Vec<serde_json::Value> = profiles
+            .iter()
+            .map(|(name, user)| {
+                serde_json::json!({
+                    "name": name,
+                    "active": *name == config.active_profile,
+                    "email": user.map(|user| &user.email),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&json)?);
+        return Ok(());
+    }
+    for (name, user) in &profiles {
+        let account = match user {
+            Some(user) => user.email.as_str(),
+            None => "not logged in",
+        };
+        if *name == config.active_profile {
+            utils::info!("{} {} ({})", "*".green(), name.bold(), account);
+        } else {
+            utils::info!("  {} ({})", name, account);
+        }
+    }
+    Ok(())
+}
+
+fn account_use_impl(config: &mut Config, name: &str) -> CommandResult {
+    config.use_profile(name.trim())?;
+    config.save()?;
+    match &config.user {
+        Some(user) => utils::info!("Switched to profile '{}' ({})", name.trim(), user.email),
+        None => utils::info!(
+            "Switched to profile '{}'. Login to it using 'pet login'",
+            name.trim()
+        ),
+    }
+    Ok(())
+}
+
+fn account_add_impl(config: &mut Config, name: &str) -> CommandResult {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The profile name must not be empty".into());
+    }
+    if !config.add_profile(name) {
+        return Err(format!("There already is a profile named '{}'", name).into());
+    }
+    config.use_profile(name)?;
+    config.save()?;
+    utils::info!(
+        "Added profile '{}' and switched to it. Login to it using 'pet login'",
+        name
+    );
+    Ok(())
+}
+
+fn account_remove_impl(config: &mut Config, name: &str) -> CommandResult {
+    config.remove_profile(name.trim())?;
+    config.save()?;
+    utils::info!("Removed profile '{}'", name.trim());
+    Ok(())
+}
+
+fn config_get_impl(config: &Config, key: &str) -> CommandResult {
+    let value = config.get_setting(key)?;
+    if ui::json_output_enabled() {
+        println!("{}", serde_json::json!({ key: value }));
+    } else {
+        utils::info!("{}", value);
+    }
+    Ok(())
+}
+
+fn config_set_impl(config: &mut Config, key: &str, value: &str) -> CommandResult {
+    config.set_setting(key, value)?;
+    config.save()?;
+    utils::info!("Set {} to '{}'", key, config.get_setting(key)?);
+    Ok(())
 }
 
+/// The update check runs after the command, so it is given up on quickly rather than making
+/// the command slower
+const UPDATE_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
 async fn check_for_updates(token: Option<&str>) -> () {
-    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
-        .with(MockingMiddleware)
-        .build();
-    let mut request = client
-        .get(utils::get_api_base_url() + UPDATE_CHECK_PATH)
-        .query(&[("curr_version", env!("CARGO_PKG_VERSION"))]);
-    if let Some(token) = token {
-        request = request.bearer_auth(token);
-    }
-    let response = request.send().await;
-    if response.is_ok() && response.as_ref().unwrap().status().is_success() {
-        let api_result = response.unwrap().json::<UpdateCheckAPIResult>().await;
-        if api_result.is_ok() {
-            let api_result = api_result.unwrap();
-            if api_result.update_available {
-                println!(
-                    "\n\x1b[33mIMPORTANT: A new version of BitPet is available! Please rerun the installation command shown on our website (it will override the existing installation): https://bitpet.dev\x1b[0m"
-                );
-            }
+    if let Ok(Ok(update)) =
+        tokio::time::timeout(UPDATE_CHECK_TIMEOUT, update::fetch_update_check(token)).await
+        && update.update_available
+    {
+        let message =
+            "IMPORTANT: A new version of BitPet is available! Please run 'pet update' to update."
+                .yellow();
+        // Keep JSON output on stdout parseable
+        if ui::json_output_enabled() {
+            eprintln!("\n{}", message);
+        } else {
+            utils::info!("\n{}", message);
         }
     }
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-    tokio::task::spawn(async {
-        ctrlc::set_handler(|| {
-            let mut stdout = std::io::stdout();
-            final_cleanup_for_terminal(&mut stdout);
-            std::process::exit(1);
-        })
-        .unwrap();
-    });
+    ctrlc::set_handler(|| ui::exit_after_interrupt()).unwrap();
 
     unsafe { std::env::set_var("RUST_BACKTRACE", "1") };
     let args = Args::parse();
+    ui::init_colors(args.no_color);
+    ui::init_json_output(args.json);
+    utils::init_log_level(args.quiet, args.verbose);
+    utils::init_assume_yes(args.yes);
 
     // Load config at startup
-    let mut config = match Config::load() {
+    let mut config = match Config::load(args.config.clone()) {
         Ok(config) => config,
         Err(e) => {
-            utils::print_error_chain(e.into());
-            std::process::exit(1);
+            let exit_code = error::CustomErrorTrait::exit_code(&e);
+            utils::print_error_chain(e.into(), None, args.error_format);
+            std::process::exit(exit_code);
         }
     };
 
+    ui::init_animations(
+        args.no_animation,
+        args.speed.unwrap_or(config.animation_speed),
+    );
+
+    theme::init_theme(config.theme_path.as_deref());
+    event_log::init_event_log(config.event_log_path.as_deref());
+    utils::init_api_base_url(args.api_url.clone(), config.api_base_url.clone());
+    utils::init_network_retries(if args.no_retry {
+        0
+    } else {
+        config.network_retries
+    });
+    utils::init_request_timeout(match args.timeout {
+        Some(secs) => std::time::Duration::from_secs(secs),
+        None => std::time::Duration::from_millis(config.request_timeout_ms),
+    });
+
+    // A 401 clears the user from the config, so remember the token for redacting errors
+    let token = config.user.as_ref().map(|user| user.token.clone());
+
     let result = match args.command {
-        Commands::Login {} => handle_login(&mut config).await,
+        Commands::Login { no_browser } => handle_login(&mut config, no_browser).await,
         Commands::Logout {} => execute_authenticated_command(&mut config, LogoutCommand).await,
         Commands::Whoami {} => execute_authenticated_command(&mut config, WhoamiCommand).await,
-        Commands::Status {} => execute_command_if_pet_exists(&mut config, StatusCommand).await,
-        Commands::Feed {} => execute_command_if_pet_exists(&mut config, FeedCommand).await,
+        Commands::Status {} => {
+            match execute_command_if_pet_exists(&mut config, StatusCommand).await {
+                Err(e) if utils::is_connection_error(e.as_ref()) => {
+                    show_cached_status(&config, e).await
+                }
+                result => result,
+            }
+        }
+        // A dry run only reads the repos, so it needs neither a login nor the API
+        Commands::Feed {
+            since,
+            repo,
+            dry_run: true,
+            ..
+        } => feed_dry_run_impl(&config, since, repo).await,
+        Commands::Feed {
+            since,
+            repo,
+            dry_run: false,
+            accept_challenges,
+            decline_challenges,
+        } => {
+            let challenge_choice = match (accept_challenges, decline_challenges) {
+                (true, _) => Some(true),
+                (_, true) => Some(false),
+                _ => None,
+            };
+            execute_command_if_pet_exists(
+                &mut config,
+                FeedCommand {
+                    since,
+                    repo,
+                    challenge_choice,
+                },
+            )
+            .await
+        }
         Commands::Play {} => execute_command_if_pet_exists(&mut config, PlayCommand).await,
-        Commands::AddRepo { path } => {
-            execute_command_if_pet_exists(&mut config, AddRepoCommand { path }).await
+        Commands::Sleep {} => execute_command_if_pet_exists(&mut config, SleepCommand).await,
+        Commands::NewPet { name } => {
+            execute_authenticated_command(&mut config, NewPetCommand { name }).await
+        }
+        Commands::RemovePet {} => {
+            execute_command_if_pet_exists(&mut config, RemovePetCommand).await
+        }
+        Commands::Export { format, out } => {
+            execute_command_if_pet_exists(&mut config, ExportCommand { format, out }).await
+        }
+        Commands::AddRepo { paths, recursive } => {
+            execute_command_if_pet_exists(&mut config, AddRepoCommand { paths, recursive }).await
         }
         Commands::RemoveRepo { path } => {
             execute_command_if_pet_exists(&mut config, RemoveRepoCommand { path }).await
         }
-        Commands::ListRepos {} => {
-            execute_command_if_pet_exists(&mut config, ListReposCommand).await
+        Commands::ListRepos { with_commits } => {
+            execute_command_if_pet_exists(&mut config, ListReposCommand { with_commits }).await
         }
         Commands::Challenge { subcommand } => match subcommand {
             commands::ChallengeSubcommand::Read {} => {
                 execute_authenticated_command(&mut config, ChallengeReadCommand).await
             }
-            commands::ChallengeSubcommand::Ans {} => {
-                execute_authenticated_command(&mut config, ChallengeAnswerCommand).await
+            commands::ChallengeSubcommand::Ans { id } => {
+                execute_authenticated_command(&mut config, ChallengeAnswerCommand { id }).await
+            }
+            commands::ChallengeSubcommand::Remove { id } => {
+                execute_authenticated_command(&mut config, ChallengeRemoveCommand { id }).await
+            }
+        },
+        Commands::History { days } => history_impl(&config, days),
+        Commands::Repos { subcommand } => match subcommand {
+            commands::ReposSubcommand::Prune {} => repos_prune_impl(&mut config),
+        },
+        Commands::Account { subcommand } => match subcommand {
+            commands::AccountSubcommand::List {} => account_list_impl(&config),
+            commands::AccountSubcommand::Use { name } => account_use_impl(&mut config, &name),
+            commands::AccountSubcommand::Add { name } => account_add_impl(&mut config, &name),
+            commands::AccountSubcommand::Remove { name } => account_remove_impl(&mut config, &name),
+        },
+        Commands::Config { subcommand } => match subcommand {
+            commands::ConfigSubcommand::Get { key } => config_get_impl(&config, &key),
+            commands::ConfigSubcommand::Set { key, value } => {
+                config_set_impl(&mut config, &key, &value)
             }
-            commands::ChallengeSubcommand::Remove {} => {
-                execute_authenticated_command(&mut config, ChallengeRemoveCommand).await
+            commands::ConfigSubcommand::Path {} => {
+                utils::info!("{}", config.path().display());
+                Ok(())
             }
         },
+        Commands::Completions { shell } => {
+            // Generated into a buffer first, as clap_complete panics on write errors such as a
+            // closed pipe
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut Args::command(), "pet", &mut script);
+            std::io::stdout().write_all(&script).map_err(|e| e.into())
+        }
+        Commands::Doctor {} => doctor::doctor(&config).await,
+        Commands::Update {} => {
+            update::self_update(config.user.as_ref().map(|user| user.token.as_str())).await
+        }
         Commands::Version {} => {
-            println!("BitPet {}", env!("CARGO_PKG_VERSION"));
+            utils::info!("BitPet {}", env!("CARGO_PKG_VERSION"));
             Ok(())
         }
     };
 
+    // Raw connection and timeout errors are confusing, so explain what they mean
+    let result = result.map_err(|e| -> Box<dyn error::CustomErrorTrait> {
+        if utils::is_timeout_error(e.as_ref()) {
+            utils::NetworkError::TimedOut(e, error::capture_backtrace()).into()
+        } else if utils::is_connection_error(e.as_ref()) {
+            utils::NetworkError::Offline(e, error::capture_backtrace()).into()
+        } else {
+            e
+        }
+    });
+
     // Handle any errors from config operations
     if let Err(e) = result {
-        utils::print_error_chain(e);
-        std::process::exit(1);
+        let exit_code = e.exit_code();
+        utils::print_error_chain(e, token.as_deref(), args.error_format);
+        std::process::exit(exit_code);
     }
 
-    if config.last_update_check_time_ms + (1000 * 60 * 60 * 24)
-        < std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_millis()
+    let update_check_disabled = config.update_check_interval_hours == 0
+        || std::env::var("BITPET_NO_UPDATE_CHECK").as_deref() == Ok("1");
+    if !update_check_disabled
+        && config.last_update_check_time_ms
+            + config.update_check_interval_hours as u128 * 1000 * 60 * 60
+            < std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_millis()
     {
         let token: Option<&str> = match config.user {
             Some(ref user) => Some(&user.token),
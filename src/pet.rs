@@ -1,22 +1,26 @@
 use chrono::Local;
+use colored::Colorize;
+use reqwest::Method;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 use crate::CommandResult;
 use crate::config::{Config, UserInfo};
 use crate::constants::{
-    CHALLENGE_ANS_PATH, DOES_PET_EXIST_PATH, FEED_PATH, PLAY_PATH, STATUS_PATH,
+    CHALLENGE_ANS_PATH, DOES_PET_EXIST_PATH, FEED_PATH, NEW_PET_PATH, PLAY_PATH, REMOVE_PET_PATH,
+    SLEEP_PATH, STATUS_PATH,
 };
+use crate::error;
 use crate::error::CustomErrorTrait;
 use crate::git;
-use crate::http_mocking::MockingMiddleware;
 use crate::ui::Animation;
 use crate::ui::get_pet_display;
 use crate::utils;
 use async_trait::async_trait;
 use serde_json::json;
 
-use crate::auth::{AuthenticatedCommand, execute_authenticated_command};
+use crate::auth::{AuthenticatedCommand, execute_authenticated_command, send_authenticated};
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Pet {
     pub user_id: String,
@@ -27,6 +31,14 @@ pub struct Pet {
     pub happiness: f64,
     pub created_at: u64,
     pub streak: u64,
+    /// Goes down as the pet plays and eats, and comes back by sleeping
+    #[serde(default = "default_energy")]
+    pub energy: f64,
+}
+
+/// Energy of pets from servers that do not send it yet
+fn default_energy() -> f64 {
+    100.0
 }
 
 impl std::fmt::Display for Pet {
@@ -50,12 +62,12 @@ pub async fn execute_command_if_pet_exists(
     #[async_trait]
     impl<C: CommandIfPetExists + Send> AuthenticatedCommand for AuthCommandIfPetExists<C> {
         async fn execute(self, user: UserInfo, config: &mut Config) -> CommandResult {
-            let does_exist = does_pet_exist(user.token.as_str(), config).await?;
+            let does_exist = does_pet_exist(config).await?;
             if !does_exist {
-                return Err(format!(
-                    "You have not yet created a pet! Please visit https://bitpet.dev/dashboard to create one."
-                )
-                .into());
+                return Err(
+                    "You have not yet created a pet! Please run 'pet new-pet' to create one."
+                        .into(),
+                );
             } else {
                 self.command.execute(user, config).await
             }
@@ -64,31 +76,76 @@ pub async fn execute_command_if_pet_exists(
     execute_authenticated_command(config, AuthCommandIfPetExists { command }).await
 }
 
-async fn does_pet_exist(
-    token: &str,
+async fn does_pet_exist(config: &mut Config) -> Result<bool, Box<dyn CustomErrorTrait>> {
+    let response = send_api_request(config, Method::GET, DOES_PET_EXIST_PATH, None).await?;
+    if response.status().as_u16() == 404 {
+        return Ok(false);
+    }
+    decode_api_response::<()>(response).await?;
+    Ok(true)
+}
+
+/// Send a request to the API with the logged in user's token. The body is sent as JSON, or as
+/// the query string of GET requests.
+async fn send_api_request(
     config: &mut Config,
-) -> Result<bool, Box<dyn CustomErrorTrait>> {
-    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
-        .with(MockingMiddleware)
-        .build();
-    let response = client
-        .get(utils::get_api_base_url() + DOES_PET_EXIST_PATH)
-        .bearer_auth(token)
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        Ok(true)
-    } else if response.status().as_u16() == 404 {
-        Ok(false)
-    } else if response.status().as_u16() == 401 {
-        config.user = None;
-        config.save()?;
-        Err(format!("Oops! Please login again!").into())
-    } else {
-        let error_text = response.text().await?;
-        Err(format!("Failed to get pet status: {}", error_text).into())
+    method: Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> Result<reqwest::Response, Box<dyn CustomErrorTrait>> {
+    let client = utils::build_client();
+    let mut request = client.request(method.clone(), utils::get_api_base_url() + path);
+    if let Some(body) = body {
+        request = if method == Method::GET {
+            request.query(&body)
+        } else {
+            request
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(&body)?)
+        };
     }
+    send_authenticated(config, request).await
+}
+
+/// Decode the JSON body of a successful response (an empty body decodes as `()`), or turn an
+/// unsuccessful one into an error with the server's message. A 429 becomes a `RateLimited`
+/// error instead.
+async fn decode_api_response<T: DeserializeOwned>(
+    response: reqwest::Response,
+) -> Result<T, Box<dyn CustomErrorTrait>> {
+    let status = response.status();
+    if status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        let retry_after_secs = response
+            .headers()
+            .get(reqwest::header::RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(utils::parse_retry_after);
+        return Err(
+            utils::NetworkError::RateLimited(retry_after_secs, error::capture_backtrace()).into(),
+        );
+    }
+    let text = response.text().await?;
+    if !status.is_success() {
+        return Err(format!("The BitPet server returned an error ({}): {}", status, text).into());
+    }
+    let text = if text.trim().is_empty() {
+        "null"
+    } else {
+        text.as_str()
+    };
+    Ok(serde_json::from_str(text)?)
+}
+
+/// Send a request to the API with the logged in user's token and decode its JSON response. A 401
+/// refreshes the token or clears the session, see `send_authenticated`.
+async fn api_request<T: DeserializeOwned>(
+    config: &mut Config,
+    method: Method,
+    path: &str,
+    body: Option<serde_json::Value>,
+) -> Result<T, Box<dyn CustomErrorTrait>> {
+    let response = send_api_request(config, method, path, body).await?;
+    decode_api_response(response).await
 }
 
 #[derive(Serialize, Deserialize)]
@@ -98,31 +155,21 @@ pub struct StatusAPIResult {
 }
 
 pub async fn get_pet_status(
-    token: &str,
     config: &mut Config,
 ) -> Result<(Pet, Animation), Box<dyn CustomErrorTrait>> {
     let timezone_offset = Local::now().offset().to_string();
-    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
-        .with(MockingMiddleware)
-        .build();
-    let response = client
-        .get(utils::get_api_base_url() + STATUS_PATH)
-        .bearer_auth(token)
-        .query(&[("timezone_offset", timezone_offset)])
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        let pet: StatusAPIResult = response.json().await?;
-        Ok((pet.pet, pet.animation))
-    } else if response.status().as_u16() == 401 {
-        config.user = None;
-        config.save()?;
-        Err(format!("Oops! Please login again!").into())
-    } else {
-        let error_text = response.text().await?;
-        Err(format!("Failed to get pet status: {}", error_text).into())
-    }
+    let pet: StatusAPIResult = api_request(
+        config,
+        Method::GET,
+        STATUS_PATH,
+        Some(json!({ "timezone_offset": timezone_offset })),
+    )
+    .await?;
+    config.record_stats(&pet.pet);
+    config.cached_pet = Some(pet.pet.clone());
+    config.cached_status_animation = Some(pet.animation.clone());
+    config.save()?;
+    Ok((pet.pet, pet.animation))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -141,24 +188,20 @@ pub struct Challenge {
     pub answer_type: ChallengeAnswerType,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub enum ChallengeAnswerType {
+    #[default]
     Text,
     File,
 }
 
-impl Default for ChallengeAnswerType {
-    fn default() -> Self {
-        Self::Text
-    }
-}
-
 impl std::fmt::Display for Challenge {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "==========\nChallenge ID: {}\n\n\x1b[34m{}\x1b[0m\n==========",
-            self.id, self.description
+            "==========\nChallenge ID: {}\n\n{}\n==========",
+            self.id,
+            self.description.blue()
         )
     }
 }
@@ -173,42 +216,30 @@ pub struct FeedAPIResult {
 }
 
 pub async fn feed_pet(
-    token: &str,
     config: &mut Config,
     commits: HashMap<String, Vec<git::Commit>>,
 ) -> Result<FeedAPIResult, Box<dyn CustomErrorTrait>> {
-    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
-        .with(MockingMiddleware)
-        .build();
     let timezone_offset = Local::now().offset().to_string();
-    let response = client
-        .post(utils::get_api_base_url() + FEED_PATH)
-        .bearer_auth(token)
-        .header("Content-Type", "application/json")
-        .body(serde_json::to_string(&json!({
+    api_request(
+        config,
+        Method::POST,
+        FEED_PATH,
+        Some(json!({
             "commits": commits,
             "timezone_offset": timezone_offset
-        }))?)
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        let api_result: FeedAPIResult = response.json().await?;
-        Ok(api_result)
-    } else if response.status().as_u16() == 401 {
-        config.user = None;
-        config.save()?;
-        Err(format!("Oops! Please login again!").into())
-    } else {
-        let error_text = response.text().await?;
-        Err(format!("Failed to get pet status: {}", error_text).into())
-    }
+        })),
+    )
+    .await
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct ChallengeAnswerAPIResult {
     pub status: ChallengeAnswerStatus,
     pub feed_result: Option<FeedAPIResult>,
+    /// Why an answer was incorrect, if the server says
+    pub feedback: Option<String>,
+    /// How many more answers can be submitted for the challenge, if it is limited
+    pub attempts_remaining: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -217,37 +248,157 @@ pub enum ChallengeAnswerStatus {
     Incorrect,
 }
 
+#[derive(Debug)]
+pub enum ChallengeAnswerError {
+    FileNotFound(String, Vec<error::Frame>),
+    NotAFile(String, Vec<error::Frame>),
+    BinaryFile(String, Vec<error::Frame>),
+    /// The answer's size and the limit, in bytes
+    TooLarge(u64, u64, Vec<error::Frame>),
+    ReadError(String, std::io::Error, Vec<error::Frame>),
+    /// The server's feedback and how many attempts are left, if it sent them
+    Incorrect(Option<String>, Option<u32>, Vec<error::Frame>),
+}
+
+error::impl_custom_error!(ChallengeAnswerError {
+    FileNotFound(_),
+    NotAFile(_),
+    BinaryFile(_),
+    TooLarge(_, _),
+    ReadError(_, _),
+    Incorrect(_, _),
+});
+
+impl std::fmt::Display for ChallengeAnswerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ChallengeAnswerError::FileNotFound(path, _) => write!(f, "File not found: {}", path),
+            ChallengeAnswerError::NotAFile(path, _) => write!(f, "Not a file: {}", path),
+            ChallengeAnswerError::BinaryFile(path, _) => write!(
+                f,
+                "{} looks like a binary file. Please submit a text file.",
+                path
+            ),
+            ChallengeAnswerError::TooLarge(size, limit, _) => write!(
+                f,
+                "The answer is too large ({} bytes, the limit is {} bytes). The limit can be changed with 'pet config set max_answer_size_bytes <bytes>'.",
+                size, limit
+            ),
+            ChallengeAnswerError::ReadError(path, e, _) => {
+                write!(f, "Could not read {}: {}", path, e)
+            }
+            ChallengeAnswerError::Incorrect(feedback, attempts_remaining, _) => {
+                write!(f, "Incorrect answer!")?;
+                if let Some(feedback) = feedback {
+                    write!(f, " {}", feedback)?;
+                }
+                match attempts_remaining {
+                    Some(0) => write!(f, "\nYou have no attempts left for this challenge."),
+                    Some(1) => write!(f, "\nPlease try again, you have 1 attempt left."),
+                    Some(attempts) => {
+                        write!(
+                            f,
+                            "\nPlease try again, you have {} attempts left.",
+                            attempts
+                        )
+                    }
+                    None => write!(f, "\nPlease try again!"),
+                }
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChallengeAnswerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ChallengeAnswerError::ReadError(_, e, _) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// Read a file to submit as a challenge answer. Files over `max_size` bytes are rejected
+/// without being read, and at most `max_size` bytes are ever read in case the file grows.
+/// Invalid UTF-8 is replaced, files with NUL bytes are rejected as binary, and a trailing
+/// newline is removed.
+pub fn read_answer_file(path: &str, max_size: u64) -> Result<String, ChallengeAnswerError> {
+    use std::io::Read;
+
+    let read_error = |e: std::io::Error| {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            ChallengeAnswerError::FileNotFound(path.to_string(), error::capture_backtrace())
+        } else {
+            ChallengeAnswerError::ReadError(path.to_string(), e, error::capture_backtrace())
+        }
+    };
+
+    let metadata = std::fs::metadata(path).map_err(read_error)?;
+    if !metadata.is_file() {
+        return Err(ChallengeAnswerError::NotAFile(
+            path.to_string(),
+            error::capture_backtrace(),
+        ));
+    }
+    if metadata.len() > max_size {
+        return Err(ChallengeAnswerError::TooLarge(
+            metadata.len(),
+            max_size,
+            error::capture_backtrace(),
+        ));
+    }
+
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)
+        .map_err(read_error)?
+        .take(max_size + 1)
+        .read_to_end(&mut bytes)
+        .map_err(read_error)?;
+    if bytes.len() as u64 > max_size {
+        return Err(ChallengeAnswerError::TooLarge(
+            bytes.len() as u64,
+            max_size,
+            error::capture_backtrace(),
+        ));
+    }
+    if bytes.contains(&0) {
+        return Err(ChallengeAnswerError::BinaryFile(
+            path.to_string(),
+            error::capture_backtrace(),
+        ));
+    }
+
+    let content = String::from_utf8_lossy(&bytes);
+    let content = content
+        .strip_suffix('\n')
+        .map(|content| content.strip_suffix('\r').unwrap_or(content))
+        .unwrap_or(&content);
+    Ok(content.to_string())
+}
+
 pub async fn submit_challenge_answer(
-    token: &str,
     config: &mut Config,
     challenge_id: String,
     answer: String,
 ) -> Result<ChallengeAnswerAPIResult, Box<dyn CustomErrorTrait>> {
-    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
-        .with(MockingMiddleware)
-        .build();
-    let response = client
-        .post(utils::get_api_base_url() + CHALLENGE_ANS_PATH)
-        .bearer_auth(token)
-        .header("Content-Type", "application/json")
-        .body(serde_json::to_string(&json!({
+    if answer.len() as u64 > config.max_answer_size_bytes {
+        return Err(ChallengeAnswerError::TooLarge(
+            answer.len() as u64,
+            config.max_answer_size_bytes,
+            error::capture_backtrace(),
+        )
+        .into());
+    }
+    api_request(
+        config,
+        Method::POST,
+        CHALLENGE_ANS_PATH,
+        Some(json!({
             "challenge_id": challenge_id,
             "answer": answer
-        }))?)
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        let api_result: ChallengeAnswerAPIResult = response.json().await?;
-        Ok(api_result)
-    } else if response.status().as_u16() == 401 {
-        config.user = None;
-        config.save()?;
-        Err(format!("Oops! Please login again!").into())
-    } else {
-        let error_text = response.text().await?;
-        Err(format!("Failed to get pet status: {}", error_text).into())
-    }
+        })),
+    )
+    .await
 }
 
 #[derive(Serialize, Deserialize)]
@@ -265,32 +416,161 @@ pub struct PlayAPIResult {
 }
 
 pub async fn play_with_pet(
-    token: &str,
     config: &mut Config,
 ) -> Result<PlayAPIResult, Box<dyn CustomErrorTrait>> {
     let timezone_offset = Local::now().offset().to_string();
-    let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
-        .with(MockingMiddleware)
-        .build();
-    let response = client
-        .post(utils::get_api_base_url() + PLAY_PATH)
-        .bearer_auth(token)
-        .header("Content-Type", "application/json")
-        .body(serde_json::to_string(&json!({
+    api_request(
+        config,
+        Method::POST,
+        PLAY_PATH,
+        Some(json!({ "timezone_offset": timezone_offset })),
+    )
+    .await
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum SleepStatus {
+    SleepSuccess,
+    NotTired,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SleepAPIResult {
+    pub animation: Option<Animation>,
+    pub status: SleepStatus,
+    pub pet: Option<Pet>,
+    pub text_before_animation: Option<String>,
+}
+
+pub async fn sleep_with_pet(
+    config: &mut Config,
+) -> Result<SleepAPIResult, Box<dyn CustomErrorTrait>> {
+    let timezone_offset = Local::now().offset().to_string();
+    api_request(
+        config,
+        Method::POST,
+        SLEEP_PATH,
+        Some(json!({ "timezone_offset": timezone_offset })),
+    )
+    .await
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct NewPetAPIResult {
+    pub pet: Pet,
+    pub animation: Option<Animation>,
+    pub text_before_animation: Option<String>,
+}
+
+pub async fn create_pet(
+    config: &mut Config,
+    name: String,
+) -> Result<NewPetAPIResult, Box<dyn CustomErrorTrait>> {
+    let timezone_offset = Local::now().offset().to_string();
+    let response = send_api_request(
+        config,
+        Method::POST,
+        NEW_PET_PATH,
+        Some(json!({
+            "name": name,
             "timezone_offset": timezone_offset
-        }))?)
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        let api_result: PlayAPIResult = response.json().await?;
-        Ok(api_result)
-    } else if response.status().as_u16() == 401 {
-        config.user = None;
-        config.save()?;
-        Err(format!("Oops! Please login again!").into())
-    } else {
-        let error_text = response.text().await?;
-        Err(format!("Failed to get pet status: {}", error_text).into())
+        })),
+    )
+    .await?;
+    if response.status().as_u16() == 409 {
+        return Err("You already have a pet!".into());
+    }
+    decode_api_response(response).await
+}
+
+pub async fn remove_pet(config: &mut Config) -> CommandResult {
+    api_request(config, Method::DELETE, REMOVE_PET_PATH, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn incorrect(attempts_remaining: Option<u32>) -> String {
+        ChallengeAnswerError::Incorrect(
+            Some("Not sorted.".to_string()),
+            attempts_remaining,
+            Vec::new(),
+        )
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn too_many_requests_is_rate_limited_with_the_retry_after_value() {
+        let response = |retry_after: Option<&str>| {
+            let mut builder = http::Response::builder().status(429);
+            if let Some(retry_after) = retry_after {
+                builder = builder.header(reqwest::header::RETRY_AFTER, retry_after);
+            }
+            reqwest::Response::from(builder.body("slow down").unwrap())
+        };
+        let retry_after_secs = |error: Box<dyn CustomErrorTrait>| {
+            let error: Box<dyn std::error::Error> = error;
+            match error.downcast_ref::<utils::NetworkError>() {
+                Some(utils::NetworkError::RateLimited(retry_after_secs, _)) => *retry_after_secs,
+                _ => panic!("not rate limited: {}", error),
+            }
+        };
+
+        let error = decode_api_response::<()>(response(Some("120")))
+            .await
+            .unwrap_err();
+        assert_eq!(retry_after_secs(error), Some(120));
+
+        let error = decode_api_response::<()>(response(Some("soon")))
+            .await
+            .unwrap_err();
+        assert_eq!(retry_after_secs(error), None);
+
+        let error = decode_api_response::<()>(response(None)).await.unwrap_err();
+        assert_eq!(retry_after_secs(error), None);
+    }
+
+    #[test]
+    fn an_incorrect_answer_decodes_its_feedback_and_attempts_remaining() {
+        let result: ChallengeAnswerAPIResult = serde_json::from_str(
+            r#"{"status": "Incorrect", "feed_result": null, "feedback": "Not sorted.", "attempts_remaining": 2}"#,
+        )
+        .unwrap();
+        assert!(matches!(result.status, ChallengeAnswerStatus::Incorrect));
+        assert!(result.feed_result.is_none());
+        assert_eq!(result.feedback.as_deref(), Some("Not sorted."));
+        assert_eq!(result.attempts_remaining, Some(2));
+
+        // Older servers send neither field
+        let result: ChallengeAnswerAPIResult =
+            serde_json::from_str(r#"{"status": "Correct", "feed_result": null}"#).unwrap();
+        assert!(matches!(result.status, ChallengeAnswerStatus::Correct));
+        assert_eq!(result.feedback, None);
+        assert_eq!(result.attempts_remaining, None);
+    }
+
+    #[test]
+    fn an_incorrect_answer_says_how_many_attempts_are_left() {
+        assert_eq!(
+            incorrect(Some(0)),
+            "Incorrect answer! Not sorted.\nYou have no attempts left for this challenge."
+        );
+        assert_eq!(
+            incorrect(Some(1)),
+            "Incorrect answer! Not sorted.\nPlease try again, you have 1 attempt left."
+        );
+        assert_eq!(
+            incorrect(Some(3)),
+            "Incorrect answer! Not sorted.\nPlease try again, you have 3 attempts left."
+        );
+        assert_eq!(
+            incorrect(None),
+            "Incorrect answer! Not sorted.\nPlease try again!"
+        );
+        assert_eq!(
+            ChallengeAnswerError::Incorrect(None, None, Vec::new()).to_string(),
+            "Incorrect answer!\nPlease try again!"
+        );
     }
 }
@@ -0,0 +1,155 @@
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::pet::Pet;
+
+/// In a line's colours, stands for the colour of the eyes for the pet's mood
+const EYES_COLOUR_PLACEHOLDER: &str = "eyes";
+/// In a line's colours, stands for `mouth_colour`
+const MOUTH_COLOUR_PLACEHOLDER: &str = "mouth";
+
+/// One value for each band of a stat: below 20, below 70, and the rest
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatGlyphs {
+    pub low: String,
+    pub medium: String,
+    pub high: String,
+}
+
+impl StatGlyphs {
+    fn new(low: &str, medium: &str, high: &str) -> StatGlyphs {
+        StatGlyphs {
+            low: low.to_string(),
+            medium: medium.to_string(),
+            high: high.to_string(),
+        }
+    }
+
+    fn for_stat(&self, stat: f64) -> &str {
+        match stat {
+            s if s < 20.0 => &self.low,
+            s if s < 70.0 => &self.medium,
+            _ => &self.high,
+        }
+    }
+}
+
+/// How the pet's face is drawn: line templates where `{eyes}` and `{mouth}` are replaced by the
+/// glyphs for its mood, and a colour for each character of each drawn line. A colour can also be
+/// `eyes` or `mouth`, for the colour of those. Missing fields are taken from the built-in cat.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PetTheme {
+    pub lines: Vec<String>,
+    pub colours: Vec<Vec<String>>,
+    /// By happiness
+    pub eyes: StatGlyphs,
+    /// Drawn while jumping, blinking or tired
+    pub closed_eyes: String,
+    /// By happiness
+    pub eye_colours: StatGlyphs,
+    /// By hunger
+    pub mouth: StatGlyphs,
+    pub mouth_colour: String,
+}
+
+impl Default for PetTheme {
+    fn default() -> Self {
+        let ear_colour = "#0000ff";
+        PetTheme {
+            lines: vec![
+                "/\\_/\\".to_string(),
+                "( {eyes} )".to_string(),
+                "=  {mouth}  =".to_string(),
+            ],
+            colours: vec![
+                vec![ear_colour, ear_colour, ear_colour, ear_colour, ""],
+                vec!["", "", EYES_COLOUR_PLACEHOLDER, "", EYES_COLOUR_PLACEHOLDER],
+                vec!["", "", "", MOUTH_COLOUR_PLACEHOLDER],
+            ]
+            .into_iter()
+            .map(|line| line.into_iter().map(String::from).collect())
+            .collect(),
+            eyes: StatGlyphs::new("x.x", "o.o", "^.^"),
+            closed_eyes: "-.-".to_string(),
+            eye_colours: StatGlyphs::new("#ff0000", "#0000ff", "#00ff00"),
+            mouth: StatGlyphs::new("U", "-", "~"),
+            mouth_colour: "#ff0000".to_string(),
+        }
+    }
+}
+
+impl PetTheme {
+    /// The face and its colours for the pet's current stats
+    pub fn render(&self, pet: &Pet, eyes_closed: bool) -> (String, Vec<Vec<String>>) {
+        let eyes = if eyes_closed {
+            &self.closed_eyes
+        } else {
+            self.eyes.for_stat(pet.happiness)
+        };
+        let face = self
+            .lines
+            .iter()
+            .map(|line| {
+                line.replace("{eyes}", eyes)
+                    .replace("{mouth}", self.mouth.for_stat(pet.hunger))
+            })
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let eye_colour = self.eye_colours.for_stat(pet.happiness);
+        let colours = self
+            .colours
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|colour| match colour.as_str() {
+                        EYES_COLOUR_PLACEHOLDER => eye_colour.to_string(),
+                        MOUTH_COLOUR_PLACEHOLDER => self.mouth_colour.clone(),
+                        _ => colour.clone(),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        (face, colours)
+    }
+}
+
+static THEME: OnceLock<PetTheme> = OnceLock::new();
+
+/// Load the theme once, from the BITPET_THEME env var or else the theme_path config. A theme
+/// that cannot be loaded is warned about, and the built-in one is used instead.
+pub fn init_theme(config_theme_path: Option<&Path>) {
+    let env_path = std::env::var_os("BITPET_THEME")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+    let Some(path) = env_path.or_else(|| config_theme_path.map(Path::to_path_buf)) else {
+        return;
+    };
+    match load_theme(&path) {
+        Ok(theme) => {
+            let _ = THEME.set(theme);
+        }
+        Err(e) => eprintln!(
+            "{}",
+            format!(
+                "Warning: Could not load the theme {} ({}). The default theme is used instead.",
+                path.display(),
+                e
+            )
+            .yellow()
+        ),
+    }
+}
+
+fn load_theme(path: &Path) -> Result<PetTheme, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&content).map_err(|e| e.to_string())
+}
+
+pub fn theme() -> &'static PetTheme {
+    THEME.get_or_init(PetTheme::default)
+}
@@ -1,17 +1,31 @@
 use crate::pet;
+use crossterm::event::{KeyCode, KeyModifiers};
 use crossterm::style::{Color, Stylize};
 use crossterm::{ExecutableCommand, QueueableCommand};
 use serde::{Deserialize, Serialize};
 use std::{
-    io::{Write, stdout},
+    io::{IsTerminal, Write, stdout},
+    sync::{
+        Arc, Mutex, MutexGuard, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
     time::Duration,
 };
+use unicode_width::UnicodeWidthStr;
 
 use crate::error::CustomErrorTrait;
 
 use crate::CommandResult;
-const BOX_WIDTH: u16 = 45;
-const BOX_HEIGHT: u16 = 10;
+use crate::utils;
+/// The box grows with the terminal up to this size, unless the content needs more room
+const MAX_BOX_WIDTH: u16 = 60;
+const MAX_BOX_HEIGHT: u16 = 16;
+/// The box never shrinks below this size, so that small images still get some margin
+const MIN_BOX_WIDTH: u16 = 20;
+const MIN_BOX_HEIGHT: u16 = 6;
+/// On a terminal too short for the content, the box shrinks down to this height (a single row
+/// inside the border) and the image is cropped to fit
+const MIN_CROPPED_BOX_HEIGHT: u16 = 3;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AnimationWindow {
@@ -29,15 +43,113 @@ pub struct Animation {
     pub fps: u32,
 }
 
-fn hex_to_rgb(hex: &str) -> Option<Color> {
-    // expect "#RRGGBB"
-    if !hex.starts_with('#') || hex.len() != 7 {
-        return None;
+static COLORS_ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Decide once whether to use colors: not if `--no-color` was passed, the NO_COLOR env var is
+/// set, or stdout is not a terminal. Must be called before anything is printed.
+pub fn init_colors(no_color_flag: bool) {
+    let enabled = !no_color_flag
+        && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && stdout().is_terminal();
+    let _ = COLORS_ENABLED.set(enabled);
+    // The `colored` crate is used for most plain text output
+    if !enabled {
+        colored::control::set_override(false);
+    }
+}
+
+/// Whether colors should be printed
+pub fn colors_enabled() -> bool {
+    *COLORS_ENABLED.get().unwrap_or(&true)
+}
+
+static JSON_OUTPUT: OnceLock<bool> = OnceLock::new();
+
+/// Decide once whether commands print their results as JSON instead of formatted text and
+/// animations. Must be called before anything is printed.
+pub fn init_json_output(json_flag: bool) {
+    let _ = JSON_OUTPUT.set(json_flag);
+}
+
+/// Whether command results should be printed as JSON
+pub fn json_output_enabled() -> bool {
+    *JSON_OUTPUT.get().unwrap_or(&false)
+}
+
+static ANIMATIONS_ENABLED: OnceLock<bool> = OnceLock::new();
+static ANIMATION_SPEED: OnceLock<f32> = OnceLock::new();
+
+/// Range that the animation speed multiplier is clamped to
+pub const MIN_ANIMATION_SPEED: f32 = 0.1;
+pub const MAX_ANIMATION_SPEED: f32 = 4.0;
+
+/// Decide once how to play animations. They are not played if `--no-animation` was passed or
+/// the BITPET_NO_ANIMATION env var is `1`, in which case only their final frame is printed as
+/// plain text. `speed` multiplies their frame rate.
+pub fn init_animations(no_animation_flag: bool, speed: f32) {
+    let enabled = !no_animation_flag && std::env::var("BITPET_NO_ANIMATION").as_deref() != Ok("1");
+    let _ = ANIMATIONS_ENABLED.set(enabled);
+    let speed = if speed.is_nan() {
+        1.0
+    } else {
+        speed.clamp(MIN_ANIMATION_SPEED, MAX_ANIMATION_SPEED)
+    };
+    let _ = ANIMATION_SPEED.set(speed);
+}
+
+fn animations_enabled() -> bool {
+    *ANIMATIONS_ENABLED.get().unwrap_or(&true)
+}
+
+fn animation_speed() -> f32 {
+    *ANIMATION_SPEED.get().unwrap_or(&1.0)
+}
+
+/// Parse a sprite colour: `#RRGGBB`, `#RGB`, a named colour like `red` or `bright_blue`, or
+/// `ansi:N` for a colour of the 256 colour palette. Anything else means no colour.
+pub fn parse_color(colour: &str) -> Option<Color> {
+    let colour = colour.trim();
+    if let Some(hex) = colour.strip_prefix('#') {
+        if !hex.is_ascii() {
+            return None;
+        }
+        return match hex.len() {
+            6 => Some(Color::Rgb {
+                r: u8::from_str_radix(&hex[0..2], 16).ok()?,
+                g: u8::from_str_radix(&hex[2..4], 16).ok()?,
+                b: u8::from_str_radix(&hex[4..6], 16).ok()?,
+            }),
+            // Each digit is repeated, so "#f80" is "#ff8800"
+            3 => Some(Color::Rgb {
+                r: u8::from_str_radix(&hex[0..1], 16).ok()? * 17,
+                g: u8::from_str_radix(&hex[1..2], 16).ok()? * 17,
+                b: u8::from_str_radix(&hex[2..3], 16).ok()? * 17,
+            }),
+            _ => None,
+        };
+    }
+    if let Some(index) = colour.strip_prefix("ansi:") {
+        return index.parse().ok().map(Color::AnsiValue);
+    }
+    match colour.to_ascii_lowercase().as_str() {
+        "black" => Some(Color::Black),
+        "red" => Some(Color::DarkRed),
+        "green" => Some(Color::DarkGreen),
+        "yellow" => Some(Color::DarkYellow),
+        "blue" => Some(Color::DarkBlue),
+        "magenta" => Some(Color::DarkMagenta),
+        "cyan" => Some(Color::DarkCyan),
+        "white" => Some(Color::Grey),
+        "grey" | "gray" => Some(Color::DarkGrey),
+        "bright_red" => Some(Color::Red),
+        "bright_green" => Some(Color::Green),
+        "bright_yellow" => Some(Color::Yellow),
+        "bright_blue" => Some(Color::Blue),
+        "bright_magenta" => Some(Color::Magenta),
+        "bright_cyan" => Some(Color::Cyan),
+        "bright_white" => Some(Color::White),
+        _ => None,
     }
-    let r = u8::from_str_radix(&hex[1..3], 16).ok()?;
-    let g = u8::from_str_radix(&hex[3..5], 16).ok()?;
-    let b = u8::from_str_radix(&hex[5..7], 16).ok()?;
-    Some(Color::Rgb { r, g, b })
 }
 
 pub struct ImageDrawnArea {
@@ -47,134 +159,239 @@ pub struct ImageDrawnArea {
     height: u16,
 }
 
+/// Size (width, height) of an area that fits every frame of the animation wherever it is moved
+pub fn get_animation_content_size(animation: &Animation) -> (u16, u16) {
+    animation
+        .windows
+        .iter()
+        .fold((0u16, 0u16), |(width, height), window| {
+            let (_, _, image_width, image_height) =
+                pad_image_and_colours(window.image.clone(), window.colours.clone(), None, None);
+            (
+                width.max(
+                    (image_width as u16)
+                        .saturating_add(window.delta_x_from_center.unsigned_abs() * 2),
+                ),
+                height.max(
+                    (image_height as u16)
+                        .saturating_add(window.delta_y_from_center.unsigned_abs() * 2),
+                ),
+            )
+        })
+}
+
 pub async fn draw_animation_in_center_of_box(animation: &Animation) -> CommandResult {
+    if utils::log_level() == utils::LogLevel::Quiet {
+        return Ok(());
+    }
+    let content_size = get_animation_content_size(animation);
+
+    let Some(last_window) = animation.windows.last() else {
+        // Nothing to draw
+        return Ok(());
+    };
+    validate_windows(&animation.windows)?;
+    // Without animations, or when stdout is not a terminal (e.g. piped, or in CI) where a box
+    // cannot be drawn, the final frame is printed once as plain text
+    if !animations_enabled() || !stdout().is_terminal() {
+        return print_frame(last_window);
+    }
+    let number_of_frames = last_window.end_frame_inclusive as usize + 1;
+
+    let mut stdout_container = StdoutContainer::new()?;
     print_in_box(
-        |stdout, curr_cursor_y, box_width, box_height, curr_frame| {
-            let mut curr_image: Option<String> = None;
-            let mut curr_colours: Option<Vec<Vec<String>>> = None;
-            let mut delta_x_from_center: i16 = 0;
-            let mut delta_y_from_center: i16 = 0;
-            for window in animation.windows.clone() {
-                if curr_frame >= window.start_frame_inclusive as usize
+        &mut stdout_container,
+        |screen, curr_cursor_y, box_width, box_height, curr_frame| {
+            let Some(window) = animation.windows.iter().find(|window| {
+                curr_frame >= window.start_frame_inclusive as usize
                     && curr_frame <= window.end_frame_inclusive as usize
-                {
-                    curr_image = Some(window.image);
-                    curr_colours = Some(window.colours);
-                    delta_x_from_center = window.delta_x_from_center;
-                    delta_y_from_center = window.delta_y_from_center;
-                    break;
-                }
-            }
-
-            assert!(curr_image.is_some());
-            assert!(curr_colours.is_some());
+            }) else {
+                return Err(format!("The animation has no image for frame {}", curr_frame).into());
+            };
 
             // Pad lines to max width for alignment
             let (padded_face, padded_colours, max_width, max_height) =
-                pad_image_and_colours(curr_image.unwrap(), curr_colours.unwrap(), None, None);
+                pad_image_and_colours(window.image.clone(), window.colours.clone(), None, None);
 
             // Position to draw face
             let start_x = (box_width / 2)
                 .saturating_sub(max_width as u16 / 2)
-                .saturating_add_signed(delta_x_from_center);
+                .saturating_add_signed(window.delta_x_from_center);
             let start_y = (curr_cursor_y + box_height / 2)
                 .saturating_sub(max_height as u16 / 2)
-                .saturating_add_signed(delta_y_from_center);
+                .saturating_add_signed(window.delta_y_from_center);
 
-            draw_image_starting_at(stdout, &padded_face, &padded_colours, start_x, start_y)
+            // Rows outside the border are cropped when the box is shorter than the image
+            let visible_rows = (curr_cursor_y + 1)..(curr_cursor_y + box_height - 1);
+
+            draw_image_starting_at(
+                screen,
+                &padded_face,
+                &padded_colours,
+                start_x,
+                start_y,
+                visible_rows,
+            )
         },
-        animation.windows.last().unwrap().end_frame_inclusive as usize + 1,
+        number_of_frames,
         Some(animation.fps),
+        content_size,
     )
 }
 
-fn draw_image_starting_at(
-    stdout: &mut StdoutContainer,
+/// Check that the windows cover every frame from 0 to the last one, in order and without
+/// overlapping, so that a malformed animation is rejected before anything is drawn
+fn validate_windows(windows: &[AnimationWindow]) -> CommandResult {
+    let mut next_frame = 0;
+    for window in windows {
+        if window.start_frame_inclusive > window.end_frame_inclusive {
+            return Err(format!(
+                "The animation is malformed: a window starts at frame {} but ends at frame {}",
+                window.start_frame_inclusive, window.end_frame_inclusive
+            )
+            .into());
+        }
+        if window.start_frame_inclusive != next_frame {
+            let problem = if window.start_frame_inclusive > next_frame {
+                "has no image for"
+            } else {
+                "has more than one image for"
+            };
+            return Err(format!(
+                "The animation is malformed: it {} frame {}",
+                problem,
+                next_frame.min(window.start_frame_inclusive)
+            )
+            .into());
+        }
+        next_frame = window.end_frame_inclusive + 1;
+    }
+    Ok(())
+}
+
+/// Print the image of a frame line by line, without moving the cursor or drawing a box
+fn print_frame(window: &AnimationWindow) -> CommandResult {
+    let (image, colours, _, _) =
+        pad_image_and_colours(window.image.clone(), window.colours.clone(), None, None);
+    if image.lines().next().is_none() {
+        return Err("The animation image is empty".into());
+    }
+    let mut stdout = stdout();
+    for (i, line) in image.lines().enumerate() {
+        if colors_enabled() {
+            let colour_line = colours.get(i).map(Vec::as_slice).unwrap_or_default();
+            writeln!(stdout, "{}", colourise_line(line, colour_line))?;
+        } else {
+            writeln!(stdout, "{}", line)?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+fn draw_image_starting_at<W: Write>(
+    out: &mut W,
     image: &str,
-    colours: &Vec<Vec<String>>,
+    colours: &[Vec<String>],
     start_x: u16,
     start_y: u16,
+    visible_rows: std::ops::Range<u16>,
 ) -> Result<ImageDrawnArea, Box<dyn CustomErrorTrait>> {
+    if image.lines().next().is_none() {
+        return Err("The animation image is empty".into());
+    }
+
     let mut colourised_image = String::new();
     {
         for (i, line) in image.lines().enumerate() {
-            let colour_line = colours.get(i).unwrap();
-            let mut curr_line = String::new();
-            for (j, ch) in line.chars().enumerate() {
-                let hex = colour_line.get(j).unwrap();
-                let styled = if hex == "" {
-                    ch.to_string()
-                } else if let Some(rgb) = hex_to_rgb(hex) {
-                    ch.to_string().with(rgb).to_string()
-                } else {
-                    ch.to_string()
-                };
-                curr_line.push_str(&styled);
-            }
+            // Rows and cells missing from the colours are drawn without colour
+            let colour_line = colours.get(i).map(Vec::as_slice).unwrap_or_default();
+            let curr_line = if colors_enabled() {
+                colourise_line(line, colour_line)
+            } else {
+                line.to_string()
+            };
             colourised_image.push_str(&curr_line);
             colourised_image.push('\n');
         }
     }
 
-    stdout
-        .stdout
-        .queue(crossterm::cursor::MoveTo(start_x, start_y))?;
-    let (orig_x, orig_y) = crossterm::cursor::position()?;
-
+    let mut drawn_rows = visible_rows.end..visible_rows.start;
     for (i, line) in colourised_image.lines().enumerate() {
-        stdout
-            .stdout
-            .queue(crossterm::cursor::MoveTo(orig_x, orig_y + i as u16))?;
-        stdout.stdout.queue(crossterm::style::Print(line))?;
+        let y = start_y + i as u16;
+        if !visible_rows.contains(&y) {
+            continue;
+        }
+        drawn_rows.start = drawn_rows.start.min(y);
+        drawn_rows.end = drawn_rows.end.max(y + 1);
+        out.queue(crossterm::cursor::MoveTo(start_x, y))?;
+        out.queue(crossterm::style::Print(line))?;
     }
 
     Ok(ImageDrawnArea {
         start_x,
-        start_y,
-        width: image.lines().map(|line| line.len()).max().unwrap() as u16,
-        height: image.lines().count() as u16,
+        start_y: drawn_rows.start,
+        width: image.lines().map(|line| line.width()).max().unwrap_or(0) as u16,
+        height: drawn_rows.end.saturating_sub(drawn_rows.start),
     })
 }
 
-fn pad_image_and_colours(
+/// Add colour escapes to each character of a line, given one colour per character
+pub fn colourise_line(line: &str, colours: &[String]) -> String {
+    let mut colourised_line = String::new();
+    for (j, ch) in line.chars().enumerate() {
+        match colours.get(j).and_then(|colour| parse_color(colour)) {
+            Some(colour) => colourised_line.push_str(&ch.to_string().with(colour).to_string()),
+            None => colourised_line.push(ch),
+        }
+    }
+    colourised_line
+}
+
+/// Centre each line of the image within the widest one. Widths are measured in terminal
+/// columns (a wide character takes 2, a combining one 0), while colours stay indexed by
+/// character, so the returned widths are in columns and each colour line has one entry per
+/// character of its padded image line.
+pub fn pad_image_and_colours(
     image: String,
     colours: Vec<Vec<String>>,
     padding_char: Option<char>,
     default_colour: Option<String>,
 ) -> (String, Vec<Vec<String>>, usize, usize) {
     let lines: Vec<&str> = image.lines().collect();
-    let max_width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+    let max_width = lines.iter().map(|line| line.width()).max().unwrap_or(0);
     let max_height = lines.len();
 
     let mut padded_face = Vec::with_capacity(max_height);
     let mut padded_colours = Vec::with_capacity(max_height);
 
     for (i, line) in lines.iter().enumerate() {
-        let line_len = line.len();
-        let pad = (max_width - line_len) / 2;
+        let line_width = line.width();
+        let pad = (max_width - line_width) / 2;
+        let trailing_pad = max_width - line_width - pad;
 
         // pad face line
         let face_line = format!(
             "{}{}{}",
             padding_char.unwrap_or(' ').to_string().repeat(pad),
             line,
-            padding_char
-                .unwrap_or(' ')
-                .to_string()
-                .repeat(max_width - line_len - pad)
+            padding_char.unwrap_or(' ').to_string().repeat(trailing_pad)
         );
         padded_face.push(face_line);
 
         // pad colour line
         let mut colour_line = vec![default_colour.clone().unwrap_or("".to_string()); pad];
         let input_colours = colours.get(i).cloned().unwrap_or_default();
-        colour_line.extend((0..line_len).map(|j| {
+        colour_line.extend((0..line.chars().count()).map(|j| {
             input_colours
                 .get(j)
                 .cloned()
                 .unwrap_or(default_colour.clone().unwrap_or("".to_string()))
         }));
-        colour_line.resize(max_width, default_colour.clone().unwrap_or("".to_string()));
+        colour_line.extend(vec![
+            default_colour.clone().unwrap_or("".to_string());
+            trailing_pad
+        ]);
         padded_colours.push(colour_line);
     }
 
@@ -186,188 +403,689 @@ fn pad_image_and_colours(
     )
 }
 
+/// Where `print_in_box` draws. Besides being written to, it answers what would otherwise be
+/// asked of the terminal, so that frames can also be drawn into something else (e.g. a buffer
+/// of a fixed size).
+pub trait Screen: Write {
+    /// Width and height, in cells
+    fn size(&mut self) -> std::io::Result<(u16, u16)>;
+    /// Column and row of the cursor, after everything written so far
+    fn cursor_position(&mut self) -> std::io::Result<(u16, u16)>;
+    /// Key presses and resizes since the last call, without waiting for any
+    fn pending_events(&mut self) -> std::io::Result<Vec<crossterm::event::Event>>;
+    /// Wait between two frames
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// The terminal, as a `Screen`. It is restored when dropped.
 pub struct StdoutContainer {
     stdout: std::io::Stdout,
 }
 
+impl StdoutContainer {
+    pub fn new() -> std::io::Result<StdoutContainer> {
+        let stdout_container = StdoutContainer { stdout: stdout() };
+        // Raw mode delivers key presses (for the playback controls) without waiting for enter.
+        // It also stops "\n" from returning the cursor to the start of the line, so "\r\n" is
+        // used.
+        if std::io::stdin().is_terminal() {
+            crossterm::terminal::enable_raw_mode()?;
+        }
+        Ok(stdout_container)
+    }
+}
+
+impl Write for StdoutContainer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+impl Screen for StdoutContainer {
+    fn size(&mut self) -> std::io::Result<(u16, u16)> {
+        crossterm::terminal::size()
+    }
+
+    fn cursor_position(&mut self) -> std::io::Result<(u16, u16)> {
+        self.stdout.flush()?;
+        crossterm::cursor::position()
+    }
+
+    fn pending_events(&mut self) -> std::io::Result<Vec<crossterm::event::Event>> {
+        let mut events = Vec::new();
+        while crossterm::event::poll(Duration::from_secs(0))? {
+            events.push(crossterm::event::read()?);
+        }
+        Ok(events)
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
 impl Drop for StdoutContainer {
     fn drop(&mut self) {
+        lock_terminal_state().rows_below_saved_position = None;
         final_cleanup_for_terminal(&mut self.stdout);
     }
 }
 
+/// What the ctrl-c handler needs to know to leave the terminal tidy while a box is on screen
+struct TerminalState {
+    /// Set while `print_in_box` is running, to where the output continues below the box
+    rows_below_saved_position: Option<u16>,
+}
+
+/// `print_in_box` holds this while it draws a frame, so that the ctrl-c handler never writes in
+/// the middle of one
+static TERMINAL_STATE: Mutex<TerminalState> = Mutex::new(TerminalState {
+    rows_below_saved_position: None,
+});
+
+fn lock_terminal_state() -> MutexGuard<'static, TerminalState> {
+    TERMINAL_STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Called on ctrl-c: waits for the frame being drawn, moves the cursor below the box if one is
+/// on screen, restores the terminal and exits
+pub fn exit_after_interrupt() -> ! {
+    // Kept locked until the process exits, so that no other frame is drawn
+    let terminal_state = lock_terminal_state();
+    let mut stdout_container = StdoutContainer { stdout: stdout() };
+    if let Some(rows) = terminal_state.rows_below_saved_position {
+        let _ = move_below_saved_position(&mut stdout_container, rows);
+    }
+    final_cleanup_for_terminal(&mut stdout_container.stdout);
+    std::process::exit(1);
+}
+
+/// Put the terminal back in the state the shell expects: cooked mode, default colours, a
+/// visible cursor at the start of a line. Safe to call more than once, since it runs both when
+/// rendering finishes and from the ctrl-c handler.
 pub fn final_cleanup_for_terminal(stdout: &mut std::io::Stdout) {
-    stdout.execute(crossterm::cursor::Show).unwrap();
+    if crossterm::terminal::is_raw_mode_enabled().unwrap_or(false) {
+        let _ = crossterm::terminal::disable_raw_mode();
+    }
+    // In case a frame was interrupted before it was shown
+    let _ = stdout.queue(crossterm::terminal::EndSynchronizedUpdate);
+    let _ = stdout.queue(crossterm::style::ResetColor);
+    let _ = stdout.queue(crossterm::cursor::Show);
+    // An interrupted animation can leave the cursor partway through a line
+    if stdout.is_terminal() && crossterm::cursor::position().is_ok_and(|(x, _)| x != 0) {
+        let _ = stdout.queue(crossterm::style::Print("\r\n"));
+    }
+    let _ = stdout.flush();
 }
 
-pub fn print_in_box<F>(
+const SPINNER_FRAMES: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+const SPINNER_FRAME_DURATION: Duration = Duration::from_millis(80);
+/// Waits shorter than this do not show the spinner at all, so that it does not flicker
+const SPINNER_DELAY: Duration = Duration::from_millis(150);
+
+/// A spinner drawn on the current line while waiting (e.g. for the API), cleared again when it
+/// is dropped. It is not drawn unless stdout is a terminal, nor with --no-animation, --quiet,
+/// --json, or --verbose (whose request logs would run into it).
+pub struct Spinner {
+    task: Option<tokio::task::JoinHandle<()>>,
+    is_drawn: Arc<AtomicBool>,
+}
+
+impl Spinner {
+    pub fn start(message: &str) -> Spinner {
+        let is_drawn = Arc::new(AtomicBool::new(false));
+        if !animations_enabled()
+            || json_output_enabled()
+            || utils::log_level() != utils::LogLevel::Normal
+            || !stdout().is_terminal()
+        {
+            return Spinner {
+                task: None,
+                is_drawn,
+            };
+        }
+
+        let message = message.to_string();
+        let task_is_drawn = is_drawn.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(SPINNER_DELAY).await;
+            let mut stdout = stdout();
+            task_is_drawn.store(true, Ordering::SeqCst);
+            let _ = stdout.execute(crossterm::cursor::Hide);
+            for frame in SPINNER_FRAMES.iter().cycle() {
+                let _ = stdout.queue(crossterm::style::Print(format!("\r{} {}", frame, message)));
+                let _ = stdout.flush();
+                tokio::time::sleep(SPINNER_FRAME_DURATION).await;
+            }
+        });
+        Spinner {
+            task: Some(task),
+            is_drawn,
+        }
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if self.is_drawn.load(Ordering::SeqCst) {
+            let mut stdout = stdout();
+            let _ = stdout.queue(crossterm::style::Print("\r"));
+            let _ = stdout.queue(crossterm::terminal::Clear(
+                crossterm::terminal::ClearType::CurrentLine,
+            ));
+            final_cleanup_for_terminal(&mut stdout);
+        }
+    }
+}
+
+/// Size of the box for the given terminal size, large enough to fit `content_size` (width,
+/// height) inside its border. A terminal that is wide enough but too short gets a shorter box,
+/// down to `MIN_CROPPED_BOX_HEIGHT`, in which the content is cropped. Returns `None` if the
+/// terminal is too small even for that.
+fn get_box_size(
+    terminal_width: u16,
+    terminal_height: u16,
+    content_size: (u16, u16),
+) -> Option<(u16, u16)> {
+    let min_width = content_size.0.saturating_add(2).max(MIN_BOX_WIDTH);
+    let min_height = content_size.1.saturating_add(2).max(MIN_BOX_HEIGHT);
+    // The box has to be strictly smaller than the terminal, since a newline follows it
+    let width = terminal_width
+        .saturating_sub(1)
+        .min(MAX_BOX_WIDTH.max(min_width));
+    let height = terminal_height
+        .saturating_sub(1)
+        .min(MAX_BOX_HEIGHT.max(min_height));
+    if width < min_width || height < MIN_CROPPED_BOX_HEIGHT {
+        return None;
+    }
+    Some((width, height))
+}
+
+/// Draw `max_number_of_frames` frames of a box sized for `content_size` on the screen, with
+/// `render_in_box` drawing each frame's content given the box's top row, width and height
+pub fn print_in_box<S, F>(
+    screen: &mut S,
     mut render_in_box: F,
     max_number_of_frames: usize,
     fps: Option<u32>,
+    content_size: (u16, u16),
 ) -> CommandResult
 where
-    F: FnMut(
-        &mut StdoutContainer,
-        u16,
-        u16,
-        u16,
-        usize,
-    ) -> Result<ImageDrawnArea, Box<dyn CustomErrorTrait>>,
+    S: Screen,
+    F: FnMut(&mut S, u16, u16, u16, usize) -> Result<ImageDrawnArea, Box<dyn CustomErrorTrait>>,
 {
-    let mut stdout_container = StdoutContainer { stdout: stdout() };
-    stdout_container.stdout.execute(crossterm::cursor::Hide)?;
-    stdout_container
-        .stdout
-        .execute(crossterm::cursor::SavePosition)?;
-    let (mut w, mut h) = crossterm::terminal::size().unwrap();
+    screen.execute(crossterm::cursor::Hide)?;
+    screen.execute(crossterm::cursor::SavePosition)?;
+    let (mut w, mut h) = screen.size()?;
     let mut frame: usize = 0;
     let mut is_showing_error = false;
     let mut older_image_drawn_area: Option<ImageDrawnArea> = None;
+    let mut box_size = get_box_size(w, h, content_size);
+    // Only the real time between frames changes with the speed, not which frames are drawn
+    let frame_duration =
+        Duration::from_secs_f32(1.0 / (fps.unwrap_or(60).max(1) as f32 * animation_speed()));
+    let mut is_paused = false;
     while frame < max_number_of_frames {
-        while crossterm::event::poll(Duration::from_secs(0))? {
-            match crossterm::event::read()? {
+        let mut should_quit = false;
+        let mut was_resized = false;
+        for event in screen.pending_events()? {
+            match event {
                 crossterm::event::Event::Resize(nw, nh) => {
                     w = nw;
                     h = nh;
+                    was_resized = true;
+                }
+                crossterm::event::Event::Key(key)
+                    if key.kind == crossterm::event::KeyEventKind::Press =>
+                {
+                    match key.code {
+                        // Raw mode swallows ctrl-c, so behave like the ctrl-c handler
+                        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                            exit_after_interrupt()
+                        }
+                        KeyCode::Char(' ') => is_paused = !is_paused,
+                        KeyCode::Char('q') | KeyCode::Esc => should_quit = true,
+                        KeyCode::Char('.') if is_paused => {
+                            frame = (frame + 1).min(max_number_of_frames - 1)
+                        }
+                        KeyCode::Char(',') if is_paused => frame = frame.saturating_sub(1),
+                        _ => {}
+                    }
                 }
-                _ => (),
+                _ => {}
             }
         }
-        stdout_container
-            .stdout
-            .execute(crossterm::cursor::RestorePosition)?;
-        if w <= BOX_WIDTH || h <= BOX_HEIGHT {
-            if !is_showing_error {
-                is_showing_error = true;
-                stdout_container
-                    .stdout
-                    .execute(crossterm::cursor::SavePosition)?;
-                stdout_container.stdout.execute(crossterm::style::Print(
-                    colored::Colorize::red("Error: Terminal too small to display your pet :("),
-                ))?;
-            }
-        } else {
+        if should_quit {
+            break;
+        }
+        // Held until the frame is drawn, so that the ctrl-c handler waits for it
+        let mut terminal_state = lock_terminal_state();
+        // Terminals that support it show the frame all at once instead of as it is written,
+        // and the rest ignore this
+        screen.queue(crossterm::terminal::BeginSynchronizedUpdate)?;
+        screen.execute(crossterm::cursor::RestorePosition)?;
+        if was_resized {
+            // The previous box (or error message) may not line up with the new layout, so clear
+            // all of it instead of only the parts of the last image that changed
+            screen.queue(crossterm::terminal::Clear(
+                crossterm::terminal::ClearType::FromCursorDown,
+            ))?;
+            older_image_drawn_area = None;
             is_showing_error = false;
-            let horizontal_border = "─".repeat(BOX_WIDTH as usize - 2);
-            stdout_container
-                .stdout
-                .queue(crossterm::style::Print(format!(
-                    "┌{}┐\n",
+        }
+        box_size = get_box_size(w, h, content_size);
+        match box_size {
+            None => {
+                if !is_showing_error {
+                    is_showing_error = true;
+                    screen.execute(crossterm::cursor::SavePosition)?;
+                    screen.execute(crossterm::style::Print(colored::Colorize::red(
+                        "Error: Terminal too small to display your pet :(",
+                    )))?;
+                }
+            }
+            Some((box_width, box_height)) => {
+                is_showing_error = false;
+                let horizontal_border = "─".repeat(box_width as usize - 2);
+                screen.queue(crossterm::style::Print(format!(
+                    "┌{}┐\r\n",
                     horizontal_border
                 )))?;
-            for _ in 0..BOX_HEIGHT - 2 {
-                stdout_container
-                    .stdout
-                    .queue(crossterm::style::Print("│"))?;
-                stdout_container
-                    .stdout
-                    .queue(crossterm::cursor::MoveRight(BOX_WIDTH as u16 - 2))?;
-                stdout_container
-                    .stdout
-                    .queue(crossterm::style::Print("│\n"))?;
-            }
-            stdout_container
-                .stdout
-                .queue(crossterm::style::Print(format!(
-                    "└{}┘\n",
+                for _ in 0..box_height - 2 {
+                    screen.queue(crossterm::style::Print("│"))?;
+                    screen.queue(crossterm::cursor::MoveRight(box_width - 2))?;
+                    screen.queue(crossterm::style::Print("│\r\n"))?;
+                }
+                screen.queue(crossterm::style::Print(format!(
+                    "└{}┘\r\n",
                     horizontal_border
                 )))?;
-            let curr_position_of_cursor = crossterm::cursor::position()?;
-            stdout_container.stdout.queue(crossterm::cursor::MoveTo(
-                0,
-                curr_position_of_cursor.1 - BOX_HEIGHT,
-            ))?;
-            stdout_container
-                .stdout
-                .queue(crossterm::cursor::SavePosition)?;
-            let image_drawn_area = render_in_box(
-                &mut stdout_container,
-                curr_position_of_cursor.1 - BOX_HEIGHT,
-                BOX_WIDTH as u16,
-                BOX_HEIGHT as u16,
-                frame,
-            )?;
-            if let Some(older_area) = older_image_drawn_area {
-                let mut areas_to_clear: Vec<(u16, u16)> = Vec::new();
-
-                for y in older_area.start_y..older_area.start_y + older_area.height {
-                    for x in older_area.start_x..older_area.start_x + older_area.width {
-                        if x < image_drawn_area.start_x
-                            || x >= image_drawn_area.start_x + image_drawn_area.width
-                            || y < image_drawn_area.start_y
-                            || y >= image_drawn_area.start_y + image_drawn_area.height
-                        {
-                            areas_to_clear.push((x, y));
-                        }
+                let curr_position_of_cursor = screen.cursor_position()?;
+                screen.queue(crossterm::cursor::MoveTo(
+                    0,
+                    curr_position_of_cursor.1 - box_height,
+                ))?;
+                screen.queue(crossterm::cursor::SavePosition)?;
+                let image_drawn_area = render_in_box(
+                    screen,
+                    curr_position_of_cursor.1 - box_height,
+                    box_width,
+                    box_height,
+                    frame,
+                )?;
+                if let Some(older_area) = older_image_drawn_area {
+                    for (x, y, length) in stale_cell_runs(&older_area, &image_drawn_area) {
+                        screen.queue(crossterm::cursor::MoveTo(x, y))?;
+                        screen.queue(crossterm::style::Print(" ".repeat(length as usize)))?;
                     }
                 }
-
-                for to_clear in areas_to_clear {
-                    stdout_container
-                        .stdout
-                        .queue(crossterm::cursor::MoveTo(to_clear.0, to_clear.1))?;
-                    stdout_container
-                        .stdout
-                        .queue(crossterm::style::Print(" "))?;
-                }
+                older_image_drawn_area = Some(image_drawn_area);
+                screen.flush()?;
             }
-            older_image_drawn_area = Some(image_drawn_area);
-            stdout_container.stdout.flush()?;
         }
+        screen.execute(crossterm::terminal::EndSynchronizedUpdate)?;
+        terminal_state.rows_below_saved_position = Some(rows_below_box(box_size));
+        drop(terminal_state);
 
-        std::thread::sleep(Duration::from_millis(1000 / fps.unwrap_or(60) as u64));
+        if is_paused {
+            screen.sleep(frame_duration);
+            continue;
+        }
         frame += 1;
+        // The last frame stays on screen, so there is nothing to wait for after it
+        if frame < max_number_of_frames {
+            screen.sleep(frame_duration);
+        }
     }
 
-    let mut dy = BOX_HEIGHT;
-    if w <= BOX_WIDTH || h <= BOX_HEIGHT {
-        dy = 2;
+    let mut terminal_state = lock_terminal_state();
+    terminal_state.rows_below_saved_position = None;
+    move_below_saved_position(screen, rows_below_box(box_size))?;
+    Ok(())
+}
+
+/// Cells of the previous image that the new one does not cover, as (x, y, length) runs of
+/// consecutive cells on the same row, so that each run is cleared with a single write
+fn stale_cell_runs(
+    older_area: &ImageDrawnArea,
+    newer_area: &ImageDrawnArea,
+) -> Vec<(u16, u16, u16)> {
+    let is_covered = |x: u16, y: u16| {
+        x >= newer_area.start_x
+            && x < newer_area.start_x + newer_area.width
+            && y >= newer_area.start_y
+            && y < newer_area.start_y + newer_area.height
+    };
+    let mut runs: Vec<(u16, u16, u16)> = Vec::new();
+    for y in older_area.start_y..older_area.start_y + older_area.height {
+        let mut run_start: Option<u16> = None;
+        for x in older_area.start_x..=older_area.start_x + older_area.width {
+            let is_stale = x < older_area.start_x + older_area.width && !is_covered(x, y);
+            match (run_start, is_stale) {
+                (None, true) => run_start = Some(x),
+                (Some(start), false) => {
+                    runs.push((start, y, x - start));
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    runs
+}
+
+/// How far below the saved cursor position (the top of the box, or the error message) the
+/// output continues
+fn rows_below_box(box_size: Option<(u16, u16)>) -> u16 {
+    match box_size {
+        Some((_, box_height)) => box_height,
+        None => 2,
     }
-    stdout_container
-        .stdout
-        .execute(crossterm::cursor::RestorePosition)?;
-    let curr_position_of_cursor = crossterm::cursor::position()?;
-    stdout_container
-        .stdout
-        .execute(crossterm::cursor::MoveTo(0, curr_position_of_cursor.1 + dy))?;
+}
+
+fn move_below_saved_position<S: Screen>(screen: &mut S, rows: u16) -> std::io::Result<()> {
+    screen.execute(crossterm::cursor::RestorePosition)?;
+    let curr_position_of_cursor = screen.cursor_position()?;
+    screen.execute(crossterm::cursor::MoveTo(
+        0,
+        curr_position_of_cursor.1 + rows,
+    ))?;
     Ok(())
 }
 
+/// Lay out the rows as lines with their columns aligned (measured in terminal columns), two
+/// spaces apart. The last column is not padded.
+pub fn format_table(rows: &[Vec<String>]) -> Vec<String> {
+    let mut column_widths: Vec<usize> = Vec::new();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            if i == column_widths.len() {
+                column_widths.push(0);
+            }
+            column_widths[i] = column_widths[i].max(cell.width());
+        }
+    }
+    rows.iter()
+        .map(|row| {
+            let mut line = String::new();
+            for (i, cell) in row.iter().enumerate() {
+                line.push_str(cell);
+                if i + 1 < row.len() {
+                    line.push_str(&" ".repeat(column_widths[i] - cell.width() + 2));
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+/// Draw the values as a line of block characters scaled between `min` and `max`, with a space
+/// for each missing value
+pub fn sparkline(values: &[Option<f64>], min: f64, max: f64) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let range = if max > min { max - min } else { 1.0 };
+    values
+        .iter()
+        .map(|value| match value {
+            Some(value) if value.is_finite() => {
+                let position = ((value - min) / range).clamp(0.0, 1.0);
+                BLOCKS[(position * (BLOCKS.len() - 1) as f64).round() as usize]
+            }
+            _ => ' ',
+        })
+        .collect()
+}
+
 pub fn get_pet_display(pet: &pet::Pet) -> String {
-    // Calculate age in days
+    // A clock before 1970, or a created_at in the future (clock skew), gives an age of 0
     let current_time = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
+        .unwrap_or_default()
         .as_millis() as u64;
-    let age_days = (current_time - pet.created_at) / (1000 * 60 * 60 * 24);
+    let age_days = current_time.saturating_sub(pet.created_at) / (1000 * 60 * 60 * 24);
 
     // Color functions for different stats
     let hunger_color = get_hunger_color(pet.hunger);
     let happiness_color = get_happiness_color(pet.happiness);
+    let energy_color = get_energy_color(pet.energy);
 
     format!(
-        "Here is how {} is feeling:\n- Level: {}\n- Hunger: {}\n- Happiness: {}\n- Coding streak days: {}\n- Age: {} days",
+        "Here is how {} is feeling:\n- Level: {}\n- Hunger: {}\n- Happiness: {}\n- Energy: {}\n- Coding streak days: {}\n- Age: {}",
         pet.name,
-        pet.level.to_string(),
+        pet.level,
         hunger_color,
         happiness_color,
-        pet.streak.to_string(),
-        age_days.to_string()
+        energy_color,
+        pet.streak,
+        format_age(age_days)
     )
 }
 
-fn get_hunger_color(hunger: f64) -> String {
-    match hunger {
-        0.0..=30.0 => format!("{:.1}", hunger).green().to_string(),
-        31.0..=75.0 => format!("{:.1}", hunger).yellow().to_string(),
-        _ => format!("{:.1}", hunger).red().to_string(),
+/// The age in the largest unit that reads naturally: days for the first two months, then
+/// months for the first two years, then years
+fn format_age(age_days: u64) -> String {
+    let (amount, unit) = match age_days {
+        0..60 => (age_days, "day"),
+        60..730 => (age_days / 30, "month"),
+        _ => (age_days / 365, "year"),
+    };
+    if amount == 1 {
+        format!("1 {}", unit)
+    } else {
+        format!("{} {}s", amount, unit)
     }
 }
 
+fn get_hunger_color(hunger: f64) -> String {
+    colour_stat(hunger, Color::Green, Color::Red)
+}
+
+fn get_energy_color(energy: f64) -> String {
+    colour_stat(energy, Color::Red, Color::Green)
+}
+
 fn get_happiness_color(happiness: f64) -> String {
-    match happiness {
-        0.0..=30.0 => format!("{:.1}", happiness).red().to_string(),
-        31.0..=75.0 => format!("{:.1}", happiness).yellow().to_string(),
-        _ => format!("{:.1}", happiness).green().to_string(),
+    colour_stat(happiness, Color::Red, Color::Green)
+}
+
+/// Colour a stat (0 to 100) by band: below 30 is `low_colour`, below 75 is yellow and anything
+/// else is `high_colour`. A NaN is left uncoloured rather than put in a band.
+fn colour_stat(value: f64, low_colour: Color, high_colour: Color) -> String {
+    let text = format!("{:.1}", value);
+    match stat_band_colour(value, low_colour, high_colour) {
+        Some(colour) if colors_enabled() => text.with(colour).to_string(),
+        _ => text,
+    }
+}
+
+fn stat_band_colour(value: f64, low_colour: Color, high_colour: Color) -> Option<Color> {
+    if value.is_nan() {
+        None
+    } else if value < 30.0 {
+        Some(low_colour)
+    } else if value < 75.0 {
+        Some(Color::Yellow)
+    } else {
+        Some(high_colour)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat_bands_have_no_gaps_at_their_boundaries() {
+        let cases = [
+            (0.0, Some(Color::Red)),
+            (29.9, Some(Color::Red)),
+            (30.0, Some(Color::Yellow)),
+            (30.5, Some(Color::Yellow)),
+            (74.9, Some(Color::Yellow)),
+            (75.0, Some(Color::Green)),
+            (100.0, Some(Color::Green)),
+            (f64::NAN, None),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(
+                stat_band_colour(value, Color::Red, Color::Green),
+                expected,
+                "value {}",
+                value
+            );
+        }
+    }
+
+    fn pet_created_at(created_at: u64) -> pet::Pet {
+        pet::Pet {
+            user_id: "user".to_string(),
+            id: "pet".to_string(),
+            name: "Bit".to_string(),
+            level: 1.0,
+            hunger: 50.0,
+            happiness: 50.0,
+            created_at,
+            streak: 0,
+            energy: 100.0,
+        }
+    }
+
+    #[test]
+    fn pet_created_in_the_future_is_zero_days_old() {
+        let display = get_pet_display(&pet_created_at(u64::MAX));
+        assert!(display.ends_with("- Age: 0 days"), "{}", display);
+    }
+
+    #[test]
+    fn age_uses_the_largest_natural_unit() {
+        assert_eq!(format_age(0), "0 days");
+        assert_eq!(format_age(1), "1 day");
+        assert_eq!(format_age(59), "59 days");
+        assert_eq!(format_age(60), "2 months");
+        assert_eq!(format_age(729), "24 months");
+        assert_eq!(format_age(730), "2 years");
+    }
+
+    #[test]
+    fn wide_characters_are_padded_by_their_width_in_columns() {
+        let image = "猫\nabcd\n🐱🐱".to_string();
+        let colours = vec![vec!["red".to_string()]; 3];
+        let (padded, padded_colours, width, height) =
+            pad_image_and_colours(image, colours, None, None);
+
+        assert_eq!((width, height), (4, 3));
+        let lines: Vec<&str> = padded.lines().collect();
+        assert_eq!(lines, [" 猫 ", "abcd", "🐱🐱"]);
+        for (line, colour_line) in lines.iter().zip(&padded_colours) {
+            assert_eq!(line.width(), 4, "{:?}", line);
+            assert_eq!(colour_line.len(), line.chars().count(), "{:?}", line);
+        }
+        assert_eq!(padded_colours[0], ["", "red", ""]);
+    }
+
+    #[test]
+    fn colour_rows_of_the_wrong_length_do_not_panic() {
+        let red = |text: &str| text.with(parse_color("red").unwrap()).to_string();
+        let colours = ["red".to_string(), "red".to_string()];
+
+        // Characters past the colours are left uncoloured, and colours past the line are unused
+        assert_eq!(
+            colourise_line("abc", &colours[..1]),
+            format!("{}bc", red("a"))
+        );
+        assert_eq!(colourise_line("a", &colours), red("a"));
+        assert_eq!(colourise_line("ab", &[]), "ab");
+
+        let image = "ab\nabcd\nabcd".to_string();
+        let colours = vec![vec!["red".to_string(); 5], vec!["red".to_string()]];
+        let (padded, padded_colours, _, _) =
+            pad_image_and_colours(image, colours, None, Some("blue".to_string()));
+        for (line, colour_line) in padded.lines().zip(&padded_colours) {
+            assert_eq!(colour_line.len(), line.chars().count(), "{:?}", line);
+        }
+        assert_eq!(padded_colours[0], ["blue", "red", "red", "blue"]);
+        assert_eq!(padded_colours[1], ["red", "blue", "blue", "blue"]);
+        assert_eq!(padded_colours[2], ["blue"; 4]);
+    }
+
+    /// A screen of a fixed size that keeps everything written to it. The cursor is always
+    /// reported on the last row, as it is right after a box is printed at the bottom.
+    struct BufferScreen {
+        output: Vec<u8>,
+    }
+
+    impl Write for BufferScreen {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Screen for BufferScreen {
+        fn size(&mut self) -> std::io::Result<(u16, u16)> {
+            Ok((80, 24))
+        }
+
+        fn cursor_position(&mut self) -> std::io::Result<(u16, u16)> {
+            Ok((0, 24))
+        }
+
+        fn pending_events(&mut self) -> std::io::Result<Vec<crossterm::event::Event>> {
+            Ok(Vec::new())
+        }
+
+        fn sleep(&mut self, _duration: Duration) {}
+    }
+
+    #[test]
+    fn cells_of_the_previous_frame_are_cleared() {
+        let mut screen = BufferScreen { output: Vec::new() };
+        let mut box_top = 0;
+        print_in_box(
+            &mut screen,
+            |screen, top, _width, _height, frame| {
+                box_top = top;
+                // A 4x2 image, then a 2x1 one in the same corner
+                let (width, height) = if frame == 0 { (4, 2) } else { (2, 1) };
+                screen.queue(crossterm::style::Print(format!("<frame {}>", frame)))?;
+                Ok(ImageDrawnArea {
+                    start_x: 2,
+                    start_y: top + 1,
+                    width,
+                    height,
+                })
+            },
+            2,
+            None,
+            (10, 5),
+        )
+        .unwrap();
+
+        let output = String::from_utf8(screen.output).unwrap();
+        let (first_frame, second_frame) = output.split_once("<frame 1>").unwrap();
+        let mut expected = Vec::new();
+        expected
+            .queue(crossterm::cursor::MoveTo(4, box_top + 1))
+            .unwrap()
+            .queue(crossterm::style::Print("  "))
+            .unwrap()
+            .queue(crossterm::cursor::MoveTo(2, box_top + 2))
+            .unwrap()
+            .queue(crossterm::style::Print("    "))
+            .unwrap();
+        let expected = String::from_utf8(expected).unwrap();
+        assert!(second_frame.contains(&expected), "{:?}", second_frame);
+        assert!(!first_frame.contains(&expected));
     }
 }
@@ -0,0 +1,223 @@
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::CommandResult;
+use crate::constants::UPDATE_CHECK_PATH;
+use crate::error;
+use crate::utils;
+
+/// Where `cargo install` builds BitPet from
+const REPOSITORY_URL: &str = "https://github.com/rishabhpoddar/bitpet-cli";
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateCheckAPIResult {
+    pub update_available: bool,
+    #[serde(default)]
+    pub latest_version: Option<String>,
+    /// Release binary for the platform given in the request, if there is one
+    #[serde(default)]
+    pub download_url: Option<String>,
+    /// Hex encoded SHA256 of the release binary
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum UpdateError {
+    NoReleaseForPlatform(String, Vec<error::Frame>),
+    ChecksumMismatch(String, String, Vec<error::Frame>),
+    ReplaceFailed(PathBuf, std::io::Error, Vec<error::Frame>),
+}
+
+error::impl_custom_error!(UpdateError {
+    NoReleaseForPlatform(_),
+    ChecksumMismatch(_, _),
+    ReplaceFailed(_, _),
+});
+
+impl std::fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpdateError::NoReleaseForPlatform(platform, _) => write!(
+                f,
+                "There is no release binary for your platform ({}). Please update using: {}",
+                platform,
+                cargo_install_command(None)
+            ),
+            UpdateError::ChecksumMismatch(expected, actual, _) => write!(
+                f,
+                "The downloaded update is corrupt (expected SHA256 {}, got {}). Your installation was not changed.",
+                expected, actual
+            ),
+            UpdateError::ReplaceFailed(path, e, _) => write!(
+                f,
+                "Could not replace {} with the update: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+}
+
+impl std::error::Error for UpdateError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UpdateError::ReplaceFailed(_, e, _) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// The platform release binaries are built for, e.g. `linux-x86_64`
+fn current_platform() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+fn cargo_install_command(version: Option<&str>) -> String {
+    match version {
+        Some(version) => format!(
+            "cargo install --git {} --tag v{} --force",
+            REPOSITORY_URL,
+            version.trim_start_matches('v')
+        ),
+        None => format!("cargo install --git {} --force", REPOSITORY_URL),
+    }
+}
+
+/// Ask the API whether there is a newer version than the running one
+pub async fn fetch_update_check(
+    token: Option<&str>,
+) -> Result<UpdateCheckAPIResult, Box<dyn error::CustomErrorTrait>> {
+    let client = utils::build_client();
+    let mut request = client
+        .get(utils::get_api_base_url() + UPDATE_CHECK_PATH)
+        .query(&[
+            ("curr_version", env!("CARGO_PKG_VERSION")),
+            ("platform", current_platform().as_str()),
+        ]);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = utils::send_with_retry(request).await?;
+    if !response.status().is_success() {
+        return Err(format!("Could not check for updates: {}", response.status()).into());
+    }
+    Ok(response.json::<UpdateCheckAPIResult>().await?)
+}
+
+/// Whether the running binary was installed with `cargo install`, in which case it is updated
+/// the same way rather than by swapping in a release binary
+fn is_cargo_install(exe_path: &Path) -> bool {
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".cargo")));
+    let Some(cargo_bin) = cargo_home.and_then(|home| home.join("bin").canonicalize().ok()) else {
+        return false;
+    };
+    exe_path.parent() == Some(cargo_bin.as_path())
+}
+
+/// Download the latest release binary for this platform, verify its checksum and replace the
+/// running executable with it
+pub async fn self_update(token: Option<&str>) -> CommandResult {
+    let update = fetch_update_check(token).await?;
+    if !update.update_available {
+        utils::info!(
+            "You are already using the latest version of BitPet ({})",
+            env!("CARGO_PKG_VERSION")
+        );
+        return Ok(());
+    }
+
+    let exe_path = std::env::current_exe()?.canonicalize()?;
+    if is_cargo_install(&exe_path) {
+        utils::info!(
+            "BitPet was installed with cargo. Please update it by running:\n  {}",
+            cargo_install_command(update.latest_version.as_deref())
+        );
+        return Ok(());
+    }
+
+    let (Some(download_url), Some(expected_sha256)) = (update.download_url, update.sha256) else {
+        return Err(UpdateError::NoReleaseForPlatform(
+            current_platform(),
+            error::capture_backtrace(),
+        )
+        .into());
+    };
+
+    utils::info!(
+        "Downloading BitPet {}...",
+        update.latest_version.as_deref().unwrap_or("(latest)")
+    );
+    let client = utils::build_client();
+    let response = utils::send_with_retry(client.get(&download_url)).await?;
+    if !response.status().is_success() {
+        return Err(format!("Could not download the update: {}", response.status()).into());
+    }
+    let binary = response.bytes().await?;
+
+    let actual_sha256: String = Sha256::digest(&binary)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if !actual_sha256.eq_ignore_ascii_case(expected_sha256.trim()) {
+        return Err(UpdateError::ChecksumMismatch(
+            expected_sha256,
+            actual_sha256,
+            error::capture_backtrace(),
+        )
+        .into());
+    }
+
+    replace_executable(&exe_path, &binary)
+        .map_err(|e| UpdateError::ReplaceFailed(exe_path.clone(), e, error::capture_backtrace()))?;
+
+    utils::info!(
+        "{}",
+        format!(
+            "BitPet was updated to {}!",
+            update
+                .latest_version
+                .as_deref()
+                .unwrap_or("the latest version")
+        )
+        .green()
+    );
+    Ok(())
+}
+
+/// Write the new binary next to the executable, with the same permissions, and rename it over
+/// the executable so that a failed update never leaves a broken installation behind
+fn replace_executable(exe_path: &Path, binary: &[u8]) -> std::io::Result<()> {
+    let file_name = exe_path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let tmp_path = exe_path.with_file_name(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+
+    let result = (|| {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(binary)?;
+        file.sync_all()?;
+        drop(file);
+        fs::set_permissions(&tmp_path, fs::metadata(exe_path)?.permissions())?;
+
+        // Windows does not allow replacing a running executable, but it can be renamed
+        if cfg!(windows) {
+            let old_path = exe_path.with_file_name(format!("{}.old", file_name));
+            let _ = fs::remove_file(&old_path);
+            fs::rename(exe_path, &old_path)?;
+        }
+        fs::rename(&tmp_path, exe_path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
@@ -1,34 +1,16 @@
 use crate::error;
 
 use crate::git;
+use crate::http_mocking::MockingMiddleware;
+use crate::http_recording::{RecordingMiddleware, ReplayMiddleware};
+use crate::ui;
 
 use colored::*;
+use sha2::{Digest, Sha256};
 use std::env;
+use std::io::{IsTerminal, Write};
 
-fn get_git_root_path(normalised_path: NormalisedGitPath) -> NormalisedGitPath {
-    assert!(git::is_git(&normalised_path));
-    let mut path = normalised_path.path();
-    if path.join(".git").exists() {
-        return NormalisedGitPath {
-            path: path.to_path_buf(),
-        };
-    }
-    while let Some(parent) = path.parent() {
-        if parent.join(".git").exists() {
-            return get_git_root_path(NormalisedGitPath {
-                path: parent.to_path_buf(),
-            });
-        }
-        path = parent;
-    }
-    let result = NormalisedGitPath {
-        path: path.to_path_buf(),
-    };
-    assert!(git::is_git(&result));
-    result
-}
-
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub struct NormalisedGitPath {
     path: std::path::PathBuf,
 }
@@ -41,28 +23,19 @@ impl std::fmt::Display for NormalisedGitPath {
 
 #[derive(Debug)]
 pub enum NormalisedPathError {
-    PathNotExists(String, String),
-    PathNotGitRepository(String, String),
-    Other(Box<dyn std::error::Error>, String),
-}
-
-impl error::WithBacktrace for NormalisedPathError {
-    fn backtrace(&self) -> &String {
-        match self {
-            NormalisedPathError::PathNotExists(_, s)
-            | NormalisedPathError::PathNotGitRepository(_, s)
-            | NormalisedPathError::Other(_, s) => s,
-        }
-    }
+    PathNotExists(String, Vec<error::Frame>),
+    /// The path and the directory the search for `.git` stopped at (the filesystem root)
+    PathNotGitRepository(String, String, Vec<error::Frame>),
+    PermissionDenied(String, Vec<error::Frame>),
+    Other(Box<dyn std::error::Error + Send + Sync>, Vec<error::Frame>),
 }
 
-impl error::CustomErrorTrait for NormalisedPathError {}
-
-impl From<NormalisedPathError> for Box<dyn error::CustomErrorTrait> {
-    fn from(error: NormalisedPathError) -> Self {
-        Box::new(error)
-    }
-}
+error::impl_custom_error!(NormalisedPathError {
+    PathNotExists(_),
+    PathNotGitRepository(_, _),
+    PermissionDenied(_),
+    Other(_),
+});
 
 impl std::fmt::Display for NormalisedPathError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -70,9 +43,16 @@ impl std::fmt::Display for NormalisedPathError {
             NormalisedPathError::PathNotExists(path, _) => {
                 write!(f, "Path does not exist: {}", path)
             }
-            NormalisedPathError::PathNotGitRepository(path, _) => {
-                write!(f, "Provided path is not a Git repository: {}", path)
-            }
+            NormalisedPathError::PathNotGitRepository(path, searched_up_to, _) => write!(
+                f,
+                "Provided path is not a Git repository: {} (no .git was found in it or any parent directory up to {})",
+                path, searched_up_to
+            ),
+            NormalisedPathError::PermissionDenied(path, _) => write!(
+                f,
+                "Permission denied while resolving {}. Please check that you can access it and its parent directories.",
+                path
+            ),
             NormalisedPathError::Other(error, _) => write!(f, "{}", error),
         }
     }
@@ -88,48 +68,45 @@ impl NormalisedGitPath {
         if path.is_empty() {
             return Err(NormalisedPathError::PathNotExists(
                 path,
-                std::backtrace::Backtrace::capture().to_string(),
+                error::capture_backtrace(),
             ));
         }
         let path = if std::path::Path::new(&path).is_absolute() {
             std::path::PathBuf::from(path)
         } else {
             env::current_dir()
-                .map_err(|e| {
-                    NormalisedPathError::Other(
-                        e.into(),
-                        std::backtrace::Backtrace::capture().to_string(),
-                    )
-                })?
+                .map_err(|e| NormalisedPathError::Other(e.into(), error::capture_backtrace()))?
                 .join(path)
         };
 
-        if !path.exists() {
-            return Err(NormalisedPathError::PathNotExists(
+        // `Path::exists` would report a directory that cannot be accessed as missing
+        let resolve_error = |e: std::io::Error| match e.kind() {
+            std::io::ErrorKind::NotFound => NormalisedPathError::PathNotExists(
                 path.display().to_string(),
-                std::backtrace::Backtrace::capture().to_string(),
-            ));
-        }
-
-        let normalised_path = NormalisedGitPath {
-            path: path.canonicalize().map_err(|e| {
-                NormalisedPathError::Other(
-                    e.into(),
-                    std::backtrace::Backtrace::capture().to_string(),
-                )
-            })?,
+                error::capture_backtrace(),
+            ),
+            std::io::ErrorKind::PermissionDenied => NormalisedPathError::PermissionDenied(
+                path.display().to_string(),
+                error::capture_backtrace(),
+            ),
+            _ => NormalisedPathError::Other(e.into(), error::capture_backtrace()),
         };
+        std::fs::metadata(&path).map_err(resolve_error)?;
+        let canonical_path = path.canonicalize().map_err(resolve_error)?;
 
-        if !git::is_git(&normalised_path) {
-            return Err(NormalisedPathError::PathNotGitRepository(
-                normalised_path.path.display().to_string(),
-                std::backtrace::Backtrace::capture().to_string(),
-            ));
+        match git::find_git_root(&canonical_path) {
+            Some(root_path) => Ok(NormalisedGitPath { path: root_path }),
+            None => Err(NormalisedPathError::PathNotGitRepository(
+                canonical_path.display().to_string(),
+                canonical_path
+                    .ancestors()
+                    .last()
+                    .unwrap_or(&canonical_path)
+                    .display()
+                    .to_string(),
+                error::capture_backtrace(),
+            )),
         }
-
-        let root_path = get_git_root_path(normalised_path);
-
-        Ok(root_path)
     }
 
     pub fn path(&self) -> &std::path::Path {
@@ -137,25 +114,634 @@ impl NormalisedGitPath {
     }
 }
 
-/// Get the API base URL from environment variables
+/// The key of a repo in per-repo config maps like `last_fed_commit_time_ms`, and in the commits
+/// sent to the API: the SHA256 of its normalised path
+pub fn repo_key(path: &NormalisedGitPath) -> String {
+    format!("{:x}", Sha256::digest(path.to_string().as_bytes()))
+}
+
+/// Expand `*` and `?` wildcards in a path, for patterns the shell did not expand (e.g. quoted
+/// ones, or on Windows). A path without wildcards is returned as it is. Hidden entries are only
+/// matched by patterns that start with a dot.
+pub fn expand_glob(pattern: &str) -> Vec<String> {
+    if !pattern.contains(['*', '?']) {
+        return vec![pattern.to_string()];
+    }
+    let mut matches = vec![std::path::PathBuf::new()];
+    for component in std::path::Path::new(pattern).components() {
+        let name = component.as_os_str().to_string_lossy().to_string();
+        if !name.contains(['*', '?']) {
+            for path in &mut matches {
+                path.push(component);
+            }
+            continue;
+        }
+        matches = matches
+            .into_iter()
+            .flat_map(|dir| {
+                let read_from = if dir.as_os_str().is_empty() {
+                    std::path::PathBuf::from(".")
+                } else {
+                    dir.clone()
+                };
+                std::fs::read_dir(read_from)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .filter(|entry_name| {
+                        (!entry_name.starts_with('.') || name.starts_with('.'))
+                            && wildcard_matches(&name, entry_name)
+                    })
+                    .map(|entry_name| dir.join(entry_name))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+    matches.sort();
+    matches
+        .into_iter()
+        .map(|path| path.display().to_string())
+        .collect()
+}
+
+/// Whether `text` matches `pattern`, where `*` matches any run of characters and `?` any one
+fn wildcard_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // Classic backtracking over the position of the last `*`
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = last_star {
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+static API_BASE_URL: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// Decide the API base URL once. The --api-url flag takes precedence over the BITPET_API_URL env
+/// var, which takes precedence over the api_base_url config. Must be called before any request
+/// is sent.
+pub fn init_api_base_url(flag_value: Option<String>, config_value: Option<String>) {
+    let env_value = env::var("BITPET_API_URL").ok();
+    let url = [flag_value, env_value, config_value]
+        .into_iter()
+        .flatten()
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .find(|url| !url.is_empty())
+        .unwrap_or_else(default_api_base_url);
+    let _ = API_BASE_URL.set(url);
+}
+
 /// Returns "http://localhost:3001" for dev builds, otherwise "https://api.bitpet.dev"
+fn default_api_base_url() -> String {
+    if cfg!(debug_assertions) {
+        "http://localhost:3001".to_string()
+    } else {
+        "https://api.bitpet.dev".to_string()
+    }
+}
+
+/// Get the API base URL that every request is sent to
 pub fn get_api_base_url() -> String {
-    env::var("BITPET_API_URL").unwrap_or_else(|_| {
-        if cfg!(debug_assertions) {
-            "http://localhost:3001".to_string()
-        } else {
-            "https://api.bitpet.dev".to_string()
+    API_BASE_URL
+        .get_or_init(|| {
+            env::var("BITPET_API_URL")
+                .ok()
+                .filter(|url| !url.trim().is_empty())
+                .unwrap_or_else(default_api_base_url)
+        })
+        .clone()
+}
+
+/// How much is printed besides errors, which are always printed
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    /// Only prompts and --json output
+    Quiet,
+    Normal,
+    /// Also request URLs, timings and other diagnostics, printed to stderr
+    Verbose,
+}
+
+static LOG_LEVEL: std::sync::OnceLock<LogLevel> = std::sync::OnceLock::new();
+
+/// Decide the log level once from the --quiet and --verbose flags. Must be called before
+/// anything is printed.
+pub fn init_log_level(quiet: bool, verbose: bool) {
+    let level = if quiet {
+        LogLevel::Quiet
+    } else if verbose {
+        LogLevel::Verbose
+    } else {
+        LogLevel::Normal
+    };
+    let _ = LOG_LEVEL.set(level);
+}
+
+pub fn log_level() -> LogLevel {
+    *LOG_LEVEL.get().unwrap_or(&LogLevel::Normal)
+}
+
+static ASSUME_YES: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+/// Decide once whether confirmation prompts are answered with yes automatically (--yes)
+pub fn init_assume_yes(yes: bool) {
+    let _ = ASSUME_YES.set(yes);
+}
+
+/// Print the prompt and read the answer, or return None if --yes was passed. Refuses instead of
+/// waiting for an answer that can never come when stdin is not a terminal.
+fn read_confirmation(prompt: &str) -> Result<Option<String>, Box<dyn error::CustomErrorTrait>> {
+    if *ASSUME_YES.get().unwrap_or(&false) {
+        return Ok(None);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(
+            "This needs confirmation, but there is no terminal to ask in. Pass --yes to confirm."
+                .into(),
+        );
+    }
+    print!("{} ", prompt);
+    std::io::stdout().flush()?;
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    Ok(Some(input.trim().to_string()))
+}
+
+/// Ask a yes/no question before a destructive action. Anything but "y" or "yes", including an
+/// empty answer, means no.
+pub fn confirm(prompt: &str) -> Result<bool, Box<dyn error::CustomErrorTrait>> {
+    let Some(answer) = read_confirmation(&format!("{} [y/N]", prompt))? else {
+        return Ok(true);
+    };
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Ask the user to type `expected` (e.g. the pet's name) to confirm an action that cannot be
+/// undone
+pub fn confirm_by_typing(
+    prompt: &str,
+    expected: &str,
+) -> Result<bool, Box<dyn error::CustomErrorTrait>> {
+    let Some(answer) = read_confirmation(prompt)? else {
+        return Ok(true);
+    };
+    Ok(answer == expected)
+}
+
+/// `println!` for informational output, which --quiet suppresses
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::utils::log_level() >= $crate::utils::LogLevel::Normal {
+            println!($($arg)*);
         }
-    })
+    };
 }
+pub(crate) use info;
 
-/// Print an error and its full chain of causes
+/// `eprintln!` for diagnostics that are only printed with --verbose
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::utils::log_level() >= $crate::utils::LogLevel::Verbose {
+            eprintln!("{}", ::colored::Colorize::dimmed(format!($($arg)*).as_str()));
+        }
+    };
+}
+pub(crate) use verbose;
+
+/// Whether requests are answered by `MockingMiddleware` instead of the server: in builds with
+/// the `mock` feature, or when the BITPET_MOCK env var is `1`
+pub fn mocking_enabled() -> bool {
+    cfg!(feature = "mock") || env::var("BITPET_MOCK").as_deref() == Ok("1")
+}
 
-pub fn print_error_chain(error: Box<dyn error::CustomErrorTrait>) {
-    eprintln!("{}", format!("Error: {}", error).red());
+/// Build the HTTP client that every request is sent with. BITPET_HTTP_REPLAY=<dir> answers
+/// requests from a recording instead of sending them, and BITPET_HTTP_RECORD=<dir> records them.
+pub fn build_client() -> reqwest_middleware::ClientWithMiddleware {
+    let timeout = request_timeout();
+    let client = reqwest::Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT.min(timeout))
+        .timeout(timeout)
+        .build()
+        .expect("Could not build the HTTP client");
+    let mut builder = reqwest_middleware::ClientBuilder::new(client);
+    if let Some(dir) = env::var_os("BITPET_HTTP_REPLAY").filter(|dir| !dir.is_empty()) {
+        builder = builder.with(ReplayMiddleware { dir: dir.into() });
+    }
+    if let Some(dir) = env::var_os("BITPET_HTTP_RECORD").filter(|dir| !dir.is_empty()) {
+        builder = builder.with(RecordingMiddleware { dir: dir.into() });
+    }
+    if mocking_enabled() {
+        builder = builder.with(MockingMiddleware);
+    }
+    builder.build()
+}
+
+/// How long a request may take in total (including reading the response), unless configured
+/// otherwise
+pub const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 30_000;
+/// How long connecting to the server may take, at most the request timeout
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+static REQUEST_TIMEOUT: std::sync::OnceLock<std::time::Duration> = std::sync::OnceLock::new();
+
+/// Set the request timeout, from the --timeout flag or the request_timeout_ms config. Must be
+/// called before any client is built.
+pub fn init_request_timeout(timeout: std::time::Duration) {
+    let _ = REQUEST_TIMEOUT.set(timeout);
+}
+
+fn request_timeout() -> std::time::Duration {
+    *REQUEST_TIMEOUT
+        .get()
+        .unwrap_or(&std::time::Duration::from_millis(
+            DEFAULT_REQUEST_TIMEOUT_MS,
+        ))
+}
 
-    let backtrace = error.backtrace();
-    if !backtrace.is_empty() {
-        eprintln!("{}", backtrace.cyan().dimmed());
+/// How many times a request is retried after a transient failure, unless configured otherwise
+pub const DEFAULT_NETWORK_RETRIES: u32 = 3;
+/// Delay before the first retry, doubled for every retry after it
+const INITIAL_RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+
+static NETWORK_RETRIES: std::sync::OnceLock<u32> = std::sync::OnceLock::new();
+
+/// Set how many times requests are retried (0 disables retries). Must be called before any
+/// request is sent.
+pub fn init_network_retries(retries: u32) {
+    let _ = NETWORK_RETRIES.set(retries);
+}
+
+/// Send a request, retrying with exponential backoff on connection errors, and also on timeouts
+/// and 5xx responses if sending it twice does no harm (see `should_retry`). Other responses,
+/// including 4xx ones, are returned as they are.
+pub async fn send_with_retry(
+    request: reqwest_middleware::RequestBuilder,
+) -> Result<reqwest::Response, reqwest_middleware::Error> {
+    let retries = *NETWORK_RETRIES.get().unwrap_or(&DEFAULT_NETWORK_RETRIES);
+    let _spinner = ui::Spinner::start("Talking to BitPet...");
+    let built_request = request.try_clone().and_then(|request| request.build().ok());
+    let method = built_request
+        .as_ref()
+        .map(|request| request.method().clone());
+    let description = if log_level() >= LogLevel::Verbose {
+        built_request
+            .map(|request| format!("{} {}", request.method(), request.url()))
+            .unwrap_or_else(|| "request".to_string())
+    } else {
+        String::new()
+    };
+    let mut delay = INITIAL_RETRY_DELAY;
+    let mut attempt = 0;
+    loop {
+        let started_at = std::time::Instant::now();
+        // The last attempt sends the original request. Requests with a streaming body cannot be
+        // cloned, so they are only sent once.
+        let Some(attempt_request) = request.try_clone().filter(|_| attempt < retries) else {
+            let result = request.send().await;
+            log_response(&description, &result, started_at);
+            return result;
+        };
+        let result = attempt_request.send().await;
+        log_response(&description, &result, started_at);
+        let failure = match &result {
+            Ok(response) if response.status().is_server_error() => {
+                Some(TransientFailure::ServerError)
+            }
+            Ok(_) => None,
+            Err(e) => transient_failure(e),
+        };
+        if !failure.is_some_and(|failure| should_retry(method.as_ref(), failure)) {
+            return result;
+        }
+        verbose!("Retrying in {} ms", delay.as_millis());
+        tokio::time::sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+fn log_response(
+    description: &str,
+    result: &Result<reqwest::Response, reqwest_middleware::Error>,
+    started_at: std::time::Instant,
+) {
+    let elapsed_ms = started_at.elapsed().as_millis();
+    match result {
+        Ok(response) => verbose!(
+            "{} -> {} ({} ms)",
+            description,
+            response.status(),
+            elapsed_ms
+        ),
+        Err(e) => verbose!("{} -> {} ({} ms)", description, e, elapsed_ms),
+    }
+}
+
+/// A failed attempt that may succeed if tried again
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TransientFailure {
+    /// The server was never reached, so nothing was done
+    Connect,
+    /// The server may or may not have handled the request
+    Timeout,
+    ServerError,
+}
+
+fn transient_failure(error: &reqwest_middleware::Error) -> Option<TransientFailure> {
+    match error {
+        reqwest_middleware::Error::Reqwest(e) if e.is_connect() => Some(TransientFailure::Connect),
+        reqwest_middleware::Error::Reqwest(e) if e.is_timeout() => Some(TransientFailure::Timeout),
+        _ => None,
+    }
+}
+
+/// Whether to send a request again after a failure. A request that may have been handled is
+/// only sent again if its method is idempotent: sending a feed or a challenge answer (POSTs)
+/// twice could feed the pet twice or use up an answer attempt.
+fn should_retry(method: Option<&reqwest::Method>, failure: TransientFailure) -> bool {
+    match failure {
+        TransientFailure::Connect => true,
+        TransientFailure::Timeout | TransientFailure::ServerError => method.is_some_and(|method| {
+            [
+                reqwest::Method::GET,
+                reqwest::Method::HEAD,
+                reqwest::Method::PUT,
+                reqwest::Method::DELETE,
+            ]
+            .contains(method)
+        }),
+    }
+}
+
+/// Whether the error, or any error in its chain of causes, is a failure to connect to the
+/// server, which usually means there is no internet connection
+pub fn is_connection_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    has_reqwest_error(error, reqwest::Error::is_connect)
+}
+
+/// Whether the error, or any error in its chain of causes, is a request that timed out
+pub fn is_timeout_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    has_reqwest_error(error, reqwest::Error::is_timeout)
+}
+
+fn has_reqwest_error(
+    error: &(dyn std::error::Error + 'static),
+    predicate: fn(&reqwest::Error) -> bool,
+) -> bool {
+    let mut source = Some(error);
+    for _ in 0..MAX_ERROR_CHAIN_DEPTH {
+        let Some(error) = source else {
+            return false;
+        };
+        if let Some(reqwest_middleware::Error::Reqwest(e)) =
+            error.downcast_ref::<reqwest_middleware::Error>()
+            && predicate(e)
+        {
+            return true;
+        }
+        if let Some(e) = error.downcast_ref::<reqwest::Error>()
+            && predicate(e)
+        {
+            return true;
+        }
+        source = error.source();
+    }
+    false
+}
+
+#[derive(Debug)]
+pub enum NetworkError {
+    /// The server could not be reached by a command that needs it
+    Offline(Box<dyn error::CustomErrorTrait>, Vec<error::Frame>),
+    /// The server did not answer within the request timeout
+    TimedOut(Box<dyn error::CustomErrorTrait>, Vec<error::Frame>),
+    /// The server answered 429, with how many seconds to wait if it said (Retry-After)
+    RateLimited(Option<u64>, Vec<error::Frame>),
+}
+
+error::impl_custom_error!(
+    NetworkError { Offline(_), TimedOut(_), RateLimited(_) },
+    exit_code = error::EXIT_CODE_NETWORK
+);
+
+impl std::fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkError::Offline(_, _) => write!(
+                f,
+                "BitPet is offline! This command needs a connection to the BitPet servers."
+            ),
+            NetworkError::TimedOut(_, _) => write!(
+                f,
+                "Request timed out after {} seconds. The BitPet servers may be slow, please try again later or allow more time with --timeout.",
+                request_timeout().as_secs_f32()
+            ),
+            NetworkError::RateLimited(Some(retry_after_secs), _) => write!(
+                f,
+                "You're doing that too fast — try again in {}s",
+                retry_after_secs
+            ),
+            NetworkError::RateLimited(None, _) => {
+                write!(
+                    f,
+                    "You're doing that too fast — try again in a little while"
+                )
+            }
+        }
+    }
+}
+
+/// The seconds to wait from a Retry-After header, which is either a number of seconds or an
+/// HTTP date
+pub fn parse_retry_after(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(secs);
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.timestamp() - chrono::Utc::now().timestamp()).max(0) as u64)
+}
+
+impl std::error::Error for NetworkError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NetworkError::Offline(error, _) | NetworkError::TimedOut(error, _) => {
+                Some(error.as_ref())
+            }
+            NetworkError::RateLimited(_, _) => None,
+        }
+    }
+}
+
+const REDACTED: &str = "***";
+
+/// Mask anything that looks like a bearer token, as well as the given token wherever it
+/// appears, so that secrets never end up in printed errors
+pub fn redact_secrets(text: &str, token: Option<&str>) -> String {
+    let mut redacted = match token {
+        Some(token) if !token.is_empty() => text.replace(token, REDACTED),
+        _ => text.to_string(),
+    };
+
+    const BEARER_PREFIX: &str = "bearer ";
+    let mut search_from = 0;
+    while let Some(index) = redacted[search_from..]
+        .to_ascii_lowercase()
+        .find(BEARER_PREFIX)
+    {
+        let token_start = search_from + index + BEARER_PREFIX.len();
+        let token_len = redacted[token_start..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "-._~+/=".contains(c)))
+            .unwrap_or(redacted.len() - token_start);
+        redacted.replace_range(token_start..token_start + token_len, REDACTED);
+        search_from = token_start + REDACTED.len();
+    }
+
+    redacted
+}
+
+/// Maximum number of nested causes printed, in case an error's source() chain loops
+const MAX_ERROR_CHAIN_DEPTH: usize = 16;
+
+/// How errors are printed to stderr
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ErrorFormat {
+    /// Colored, human readable text
+    #[default]
+    Text,
+    /// A single JSON object with `message`, `backtrace` and `causes` fields
+    Json,
+}
+
+/// Print an error and its full chain of causes, with the given token and any bearer tokens
+/// redacted
+pub fn print_error_chain(
+    error: Box<dyn error::CustomErrorTrait>,
+    token: Option<&str>,
+    format: ErrorFormat,
+) {
+    let message = redact_secrets(&error.to_string(), token);
+
+    let mut causes = Vec::new();
+    let mut previous_message = error.to_string();
+    let mut source = error.source();
+    for _ in 0..MAX_ERROR_CHAIN_DEPTH {
+        let Some(cause) = source else {
+            break;
+        };
+        let cause_message = cause.to_string();
+        // Wrappers like ErrorWithBacktrace report the wrapped error as their source, which
+        // would otherwise repeat the same message.
+        if cause_message != previous_message {
+            causes.push(redact_secrets(&cause_message, token));
+        }
+        previous_message = cause_message;
+        source = cause.source();
+    }
+
+    let backtrace: Vec<error::Frame> = error
+        .backtrace()
+        .iter()
+        .map(|frame| error::Frame {
+            file: redact_secrets(&frame.file, token),
+            line: frame.line,
+            function: redact_secrets(&frame.function, token),
+        })
+        .collect();
+
+    match format {
+        ErrorFormat::Text => {
+            eprintln!("{}", format!("Error: {}", message).red());
+            if !causes.is_empty() {
+                eprintln!("{}", "Caused by:".red());
+                for (i, cause) in causes.iter().enumerate() {
+                    eprintln!("{}", format!("    {}: {}", i, cause).red());
+                }
+            }
+            if !backtrace.is_empty() {
+                eprintln!("{}", "Backtrace:".cyan().dimmed());
+                for (i, frame) in backtrace.iter().enumerate() {
+                    eprintln!("{}", format!("{:>4}: {}", i, frame).cyan().dimmed());
+                }
+            }
+        }
+        ErrorFormat::Json => {
+            eprintln!(
+                "{}",
+                serde_json::json!({
+                    "message": message,
+                    "backtrace": backtrace,
+                    "causes": causes,
+                })
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::Method;
+
+    #[test]
+    fn retry_after_is_read_as_seconds_or_an_http_date() {
+        assert_eq!(parse_retry_after("120"), Some(120));
+        assert_eq!(parse_retry_after(" 7 "), Some(7));
+
+        let in_a_minute = chrono::Utc::now() + chrono::Duration::seconds(60);
+        let secs = parse_retry_after(&in_a_minute.format("%a, %d %b %Y %H:%M:%S GMT").to_string());
+        assert!(matches!(secs, Some(58..=60)), "{:?}", secs);
+        // A date that has already passed means there is no need to wait
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(0));
+
+        assert_eq!(parse_retry_after(""), None);
+        assert_eq!(parse_retry_after("-5"), None);
+        assert_eq!(parse_retry_after("in a minute"), None);
+    }
+
+    #[test]
+    fn only_idempotent_requests_are_retried_once_they_may_have_been_handled() {
+        let cases = [
+            (Some(Method::GET), true, true),
+            (Some(Method::HEAD), true, true),
+            (Some(Method::PUT), true, true),
+            (Some(Method::DELETE), true, true),
+            (Some(Method::POST), true, false),
+            (Some(Method::PATCH), true, false),
+            // A request that could not be built to find out its method
+            (None, true, false),
+        ];
+        for (method, on_connect, on_timeout_or_5xx) in cases {
+            assert_eq!(
+                should_retry(method.as_ref(), TransientFailure::Connect),
+                on_connect,
+                "{:?}",
+                method
+            );
+            for failure in [TransientFailure::Timeout, TransientFailure::ServerError] {
+                assert_eq!(
+                    should_retry(method.as_ref(), failure),
+                    on_timeout_or_5xx,
+                    "{:?} {:?}",
+                    method,
+                    failure
+                );
+            }
+        }
     }
 }